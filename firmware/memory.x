@@ -1,11 +1,16 @@
 MEMORY
 {
-  /* First 8KB used by bootloader */
-  FLASH (rx) : ORIGIN = 0x00000000 + 8K, LENGTH = 128K - 8K
+  /* First 8KB used by bootloader, last 4KB used by the settings */
+  FLASH (rx) : ORIGIN = 0x00000000 + 8K, LENGTH = 128K - 8K - 4K
 
   /* Use this instead if you don't have a bootloader */
-  /*FLASH (rx) : ORIGIN = 0x00000000, LENGTH = 128K*/
+  /*FLASH (rx) : ORIGIN = 0x00000000, LENGTH = 128K - 4K*/
+
+  SETTINGS (r) : ORIGIN = 0x00000000 + 128K - 4K, LENGTH = 4K
 
   RAM (rwx) : ORIGIN = 0x20000000, LENGTH = 16K
 }
 _stack_start = ORIGIN(RAM) + LENGTH(RAM);
+_ram_end = ORIGIN(RAM) + LENGTH(RAM);
+_settings_start = ORIGIN(SETTINGS);
+_settings_end = ORIGIN(SETTINGS) + LENGTH(SETTINGS);
@@ -0,0 +1,2 @@
+#!/usr/bin/env bash
+nix-shell --run 'cd stuff && cargo run --example render --target x86_64-unknown-linux-gnu -- ../keymap'
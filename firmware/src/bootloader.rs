@@ -0,0 +1,28 @@
+//! Handing over to the UF2 bootloader, that stays in itself after a
+//! reset when it finds a magic value in the last word of the RAM.
+
+use core::ptr;
+use cortex_m::peripheral::SCB;
+
+extern "C" {
+    // Defined in memory.x
+    static _ram_end: u32;
+}
+
+/// Tells the bootloader to stay in itself, rather than starting the
+/// firmware.
+const MAGIC: u32 = 0xf01669ef;
+
+/// Resets into the bootloader.
+pub fn enter() -> ! {
+    unsafe {
+        let end = &_ram_end as *const u32 as usize;
+        ptr::write_volatile((end - 4) as *mut u32, MAGIC);
+    }
+    SCB::sys_reset()
+}
+
+/// Resets, starting the firmware again.
+pub fn reset() -> ! {
+    SCB::sys_reset()
+}
@@ -29,9 +29,7 @@ use keyberon::{
     action::{k, l, m, Action, Action::*},
     debounce::Debouncer,
     impl_heterogenous_array,
-    key_code::KbHidReport,
-    key_code::KeyCode,
-    layout::{Event, Layout},
+    hid::HidClass,
     matrix::{Matrix, PressedKeys},
 };
 use nb::block;
@@ -43,9 +41,23 @@ use usb_device::{
 };
 use stuff::{
     codec::{encode_scan, decode_scan, SOF, RX_BUF_LEN},
-    layers::LAYERS,
+    dynamic_macro::Macro,
+    keyboard::{Keyboard, Reboot},
+    layers::{
+        AUTO_SHIFT, BASE_LAYERS, BOOT_KEY, COMBOS, CONDITIONAL_LAYERS, KEY_OVERRIDES, LAYERS, LEADER,
+        LED_STATES, MOUSE_KEYS,
+    },
+    leds::{HostLeds, Status, StatusLed},
+    settings::Settings,
+    storage::Store,
 };
 
+mod bootloader;
+mod media;
+mod mouse;
+mod nkro;
+mod nvm;
+
 trait ResultExt<T> {
     fn get(self) -> T;
 }
@@ -58,6 +70,16 @@ impl<T> ResultExt<T> for Result<T, Infallible> {
     }
 }
 
+/// The blink code of the base layout of `default_layer`: once for the
+/// first base layout, twice for the second, and so on.
+fn base_layout(default_layer: u8) -> u8 {
+    let layout = BASE_LAYERS
+        .iter()
+        .position(|&l| l == default_layer as usize)
+        .unwrap_or(0);
+    layout as u8 + 1
+}
+
 //define_pins!(
 //    /// Maps the pins to their arduino names and
 //    /// the numbers printed on the board.
@@ -124,15 +146,23 @@ impl_heterogenous_array! {
 const APP: () = {
     struct Resources {
         usb_dev: UsbDevice<'static, UsbBus>,
-        usb_class: keyberon::Class<'static, UsbBus, ()>,
+        usb_class: keyberon::Class<'static, UsbBus, HostLeds>,
+        nkro_class: nkro::Nkro<'static, UsbBus>,
+        mouse_class: HidClass<'static, UsbBus, mouse::Mouse>,
+        media_class: HidClass<'static, UsbBus, media::Media>,
         matrix: Matrix<Cols, Rows>,
         debouncer: Debouncer<PressedKeys<U4, U7>>,
         other_debouncer: Debouncer<PressedKeys<U4, U7>>,
-        layout: Layout,
+        keyboard: Keyboard,
+        settings: Settings,
+        store: Option<Store<nvm::Nvm>>,
         timer: TimerCounter<TC3>,
         rx: atsamd_hal::sercom::Rx0,
         tx: atsamd_hal::sercom::Tx0,
         led: Pa27<Output<OpenDrain>>,
+        status_led: StatusLed,
+        /// Time since the last frame of the other half, in ms.
+        since_frame: u16,
     }
 
     #[init]
@@ -146,6 +176,20 @@ const APP: () = {
             &mut c.device.NVMCTRL,
         );
         clocks.configure_gclk_divider_and_source(GEN_A::GCLK2, 1, SRC_A::DFLL48M, false);
+        let store = Store::mount(nvm::Nvm::new(c.device.NVMCTRL), nvm::SECTOR_SIZE).ok();
+        let mut settings = store.as_ref().map(Settings::load).unwrap_or_default();
+        // A default layer saved with another order of the layers may no
+        // longer be a base one.
+        if !BASE_LAYERS.contains(&(settings.default_layer as usize)) {
+            settings.default_layer = BASE_LAYERS[0] as u8;
+        }
+        let macros = core::array::from_fn(|slot| {
+            store
+                .as_ref()
+                .map(|store| Macro::load(store, slot))
+                .unwrap_or_default()
+        });
+
         let gclk0 = clocks.gclk0();
         let gclk2 = clocks
             .get_gclk(GEN_A::GCLK2)
@@ -171,18 +215,13 @@ const APP: () = {
         )
         .unwrap();
 
-        // Enter bootloader if Escape key is pressed when keyboard is plugged in
-        let mut init_layout = Layout::new(LAYERS);
+        // Enter bootloader if the boot key is pressed when keyboard is plugged in
         let scan = matrix.get().unwrap();
-        for (i, j) in scan.iter_pressed() {
-            init_layout.event(Event::Press(i as u8, (6 - j) as u8));
-        }
-        init_layout.tick();
-        if init_layout.keycodes().any(|k| k == KeyCode::Escape ) {
-            // Set special value in last word of RAM for UF2 bootloader to see
-            unsafe { *((0x20000000 + (16 * 1024) - 4) as *mut u32) = 0xf01669ef; }
-            // Then reset the CPU to enter the bootloader
-            cortex_m::peripheral::SCB::sys_reset();
+        if scan
+            .iter_pressed()
+            .any(|(i, j)| (i as u8, (6 - j) as u8) == BOOT_KEY)
+        {
+            bootloader::enter();
         }
 
         let usb_clock = &clocks.usb(&gclk0).unwrap();
@@ -196,7 +235,10 @@ const APP: () = {
             )));
             USB_BUS.as_ref().unwrap()
         };
-        let usb_class = keyberon::new_class(usb_bus, ());
+        let usb_class = keyberon::new_class(usb_bus, HostLeds::default());
+        let nkro_class = nkro::Nkro::new(usb_bus);
+        let mouse_class = HidClass::new(mouse::Mouse::default(), usb_bus);
+        let media_class = HidClass::new(media::Media::default(), usb_bus);
         let usb_dev = keyberon::new_device(usb_bus);
 
 
@@ -232,30 +274,67 @@ const APP: () = {
 
         let mut led = port.pa27.into_open_drain_output(&mut port.port);
         led.set_high().unwrap();
-
+        let status_led = StatusLed::new(LED_STATES, base_layout(settings.default_layer));
 
         init::LateResources {
             usb_dev,
             usb_class,
+            nkro_class,
+            mouse_class,
+            media_class,
             timer,
-            debouncer: Debouncer::new(PressedKeys::default(), PressedKeys::default(), 5),
-            other_debouncer: Debouncer::new(PressedKeys::default(), PressedKeys::default(), 5),
+            debouncer: Debouncer::new(
+                PressedKeys::default(),
+                PressedKeys::default(),
+                settings.debounce,
+            ),
+            other_debouncer: Debouncer::new(
+                PressedKeys::default(),
+                PressedKeys::default(),
+                settings.debounce,
+            ),
             matrix,
-            layout: Layout::new(LAYERS),
+            keyboard: Keyboard::new(LAYERS, &settings)
+                .with_combos(COMBOS)
+                .with_base_layers(BASE_LAYERS)
+                .with_conditional_layers(CONDITIONAL_LAYERS)
+                .with_key_overrides(KEY_OVERRIDES)
+                .with_macros(macros)
+                .with_leader(&LEADER)
+                .with_auto_shift(&AUTO_SHIFT)
+                .with_mouse_keys(&MOUSE_KEYS),
+            settings,
+            store,
             rx,
             tx,
             led,
+            status_led,
+            since_frame: 0,
         }
     }
 
-    #[task(binds = USB, priority = 4, resources = [usb_dev, usb_class])]
+    #[task(
+        binds = USB,
+        priority = 4,
+        resources = [usb_dev, usb_class, nkro_class, mouse_class, media_class],
+    )]
     fn usb_rx(c: usb_rx::Context) {
-        if c.resources.usb_dev.poll(&mut [c.resources.usb_class]) {
-            c.resources.usb_class.poll();
+        let r = c.resources;
+        // The NKRO class first, to see the protocol selected on the boot
+        // keyboard interface before its class accepts it
+        let polled = r.usb_dev.poll(&mut [
+            &mut *r.nkro_class as &mut dyn UsbClass<UsbBus>,
+            &mut *r.usb_class,
+            &mut *r.mouse_class,
+            &mut *r.media_class,
+        ]);
+        if polled {
+            r.usb_class.poll();
         }
     }
 
-    #[task(binds = SERCOM0, priority = 3, spawn = [handle_uart_frame], resources = [rx])]
+    // Above the handling of the frames, so that no byte is lost meanwhile
+    #[task(binds = SERCOM0, priority = 4, spawn = [handle_uart_frame], resources = [rx])]
     fn rx(c: rx::Context) {
         static mut BUF: [u8; RX_BUF_LEN] = [0; RX_BUF_LEN];
         static mut BUF_POS: usize = 0;
@@ -274,47 +353,147 @@ const APP: () = {
         }
     }
 
-    #[task(priority = 2, capacity = 1, spawn = [handle_event], resources = [other_debouncer])]
-    fn handle_uart_frame(mut c: handle_uart_frame::Context, buf: [u8; RX_BUF_LEN]) {
+    #[task(
+        priority = 3,
+        capacity = 1,
+        resources = [other_debouncer, since_frame, keyboard],
+    )]
+    fn handle_uart_frame(c: handle_uart_frame::Context, buf: [u8; RX_BUF_LEN]) {
         if let Some(scan) = decode_scan(&buf) {
+            *c.resources.since_frame = 0;
             for event in c.resources.other_debouncer.events(scan) {
                 let event = event.transform(|i, j| (i, j + 7));
-                c.spawn.handle_event(Some(event)).unwrap();
+                c.resources.keyboard.event(event);
             }
         }
     }
  
-    #[task(priority = 2, capacity = 8, resources = [
-        usb_dev, usb_class, layout,
-        led
+    // Above the scan, so that it is done before the next tick spawns it
+    // again: its capacity of 1 is always enough.
+    #[task(priority = 3, capacity = 1, spawn = [persist, persist_macro], resources = [
+        usb_dev, usb_class, nkro_class, mouse_class, media_class, keyboard, settings,
+        led, status_led, since_frame
         ])]
-    fn handle_event(mut c: handle_event::Context, event: Option<Event>) {
-        if let Some(event) = event {
-            c.resources.layout.event(event);
-            c.resources.led.toggle();
+    fn handle_tick(mut c: handle_tick::Context) {
+        static mut UNSENT: bool = false;
+        static mut NKRO_UNSENT: bool = false;
+
+        c.resources.keyboard.tick();
+        *c.resources.since_frame = c.resources.since_frame.saturating_add(1);
+        let status = Status {
+            host: c.resources.usb_class.lock(|k| *k.device_mut().leds_mut()),
+            layer: c.resources.keyboard.current_layer(),
+            default_layer: c.resources.keyboard.default_layer(),
+            since_frame: *c.resources.since_frame,
+            bootloader: c.resources.keyboard.reboot_pending() == Some(Reboot::Bootloader),
+        };
+        // Open drain, lit when low
+        if c.resources.status_led.tick(&status) {
+            c.resources.led.set_low().unwrap();
+        } else {
+            c.resources.led.set_high().unwrap();
+        }
+        let settings = Settings {
+            default_layer: c.resources.keyboard.default_layer() as u8,
+            os: c.resources.keyboard.os() as u8,
+            unicode_input: c.resources.keyboard.unicode_input() as u8,
+            features: c.resources.keyboard.features(),
+            ..*c.resources.settings
         };
-        c.resources.layout.tick();
-        let report: KbHidReport = c.resources.layout.keycodes().collect();
-        if !c
+        if settings != *c.resources.settings {
+            // Tried again at the next tick if still saving
+            if c.spawn.persist(settings).is_ok() {
+                if settings.default_layer != c.resources.settings.default_layer {
+                    c.resources.status_led.show(base_layout(settings.default_layer));
+                }
+                *c.resources.settings = settings;
+            }
+        }
+        if let Some((slot, recorded)) = c.resources.keyboard.unsaved_macro() {
+            if c.spawn.persist_macro(slot, recorded.clone()).is_ok() {
+                c.resources.keyboard.macro_saved();
+            }
+        }
+        let boot_protocol = c.resources.nkro_class.lock(|k| k.boot_protocol());
+        c.resources.keyboard.set_boot_protocol(boot_protocol);
+        let report = c.resources.keyboard.report();
+        let changed = c
             .resources
             .usb_class
-            .lock(|k| k.device_mut().set_keyboard_report(report.clone()))
-        {
-            return;
+            .lock(|k| k.device_mut().set_keyboard_report(report.clone()));
+        // Written again at the next ticks while the host hasn't read the
+        // previous report, so that no report is lost.
+        let configured = c.resources.usb_dev.lock(|d| d.state()) == UsbDeviceState::Configured;
+        if (changed || *UNSENT) && configured {
+            let written = c.resources.usb_class.lock(|k| k.write(report.as_bytes()));
+            *UNSENT = !matches!(written, Ok(n) if n > 0);
+        }
+        // Likewise for the NKRO report, empty but in NKRO mode with the
+        // report protocol
+        let nkro_report = c.resources.keyboard.nkro_report();
+        let changed = c.resources.nkro_class.lock(|k| k.set_report(nkro_report.clone()));
+        if (changed || *NKRO_UNSENT) && configured {
+            let written = c
+                .resources
+                .nkro_class
+                .lock(|k| k.write(nkro_report.as_bytes()));
+            *NKRO_UNSENT = !matches!(written, Ok(n) if n > 0);
+        }
+        if !*UNSENT && !*NKRO_UNSENT {
+            c.resources.keyboard.report_sent();
+        }
+        // The motion adds up in the keyboard until written
+        if let Some(report) = c.resources.keyboard.mouse_report() {
+            let written = c.resources.mouse_class.lock(|m| {
+                m.device_mut().set_report(&report);
+                m.write(&report.as_bytes())
+            });
+            if configured && matches!(written, Ok(n) if n > 0) {
+                c.resources.keyboard.mouse_report_sent(&report);
+            }
+        }
+        // Sent only when a control changes
+        if let Some(report) = c.resources.keyboard.media_report() {
+            let written = c.resources.media_class.lock(|m| {
+                m.device_mut().set_report(&report);
+                m.write(&report.as_bytes())
+            });
+            if configured && matches!(written, Ok(n) if n > 0) {
+                c.resources.keyboard.media_report_sent(&report);
+            }
         }
-        if c.resources.usb_dev.lock(|d| d.state()) != UsbDeviceState::Configured {
-            return;
+        match c.resources.keyboard.reboot() {
+            Some(Reboot::Bootloader) => bootloader::enter(),
+            Some(Reboot::Reset) => bootloader::reset(),
+            None => (),
+        }
+    }
+
+    /// Saves the settings, below the scan as writing the flash takes a
+    /// while. The flash can't be read while a word is written or a row
+    /// erased, stalling the scan, but it goes on between them.
+    #[task(priority = 1, capacity = 1, resources = [store])]
+    fn persist(c: persist::Context, settings: Settings) {
+        if let Some(store) = c.resources.store {
+            let _ = settings.save(store);
+        }
+    }
+
+    /// Saves a dynamic macro once recorded, as `persist` does.
+    #[task(priority = 1, capacity = 1, resources = [store])]
+    fn persist_macro(c: persist_macro::Context, slot: usize, recorded: Macro) {
+        if let Some(store) = c.resources.store {
+            let _ = recorded.save(store, slot);
         }
-        while let Ok(0) = c.resources.usb_class.lock(|k| k.write(report.as_bytes())) {}
     }
 
     #[task(
         binds = TC3,
-        priority = 1,
-        spawn = [handle_event],
-        resources = [matrix, debouncer, timer, tx],
+        priority = 2,
+        spawn = [handle_tick],
+        resources = [matrix, debouncer, timer, tx, keyboard],
     )]
-    fn tick(c: tick::Context) {
+    fn tick(mut c: tick::Context) {
         c.resources.timer.wait().ok();
 
         let scan = c.resources.matrix.get().unwrap();
@@ -327,11 +506,13 @@ const APP: () = {
                 .map_err(|_| nb::Error::<()>::WouldBlock));
         }
 
+        // The events are queued in the keyboard, and processed by its
+        // ticks, one per ms
         for event in c.resources.debouncer.events(scan) {
             let event = event.transform(|i, j| (i, 6 - j));
-            c.spawn.handle_event(Some(event)).unwrap();
+            c.resources.keyboard.lock(|k| k.event(event));
         }
-        c.spawn.handle_event(None).unwrap();
+        c.spawn.handle_tick().unwrap();
     }
 
     // Unused interrupts that will be used by RTIC for software tasks
@@ -0,0 +1,81 @@
+//! The HID interface of the media keys, with the consumer control and
+//! the system control reports.
+
+use keyberon::hid::{HidDevice, Protocol, ReportType, Subclass};
+use stuff::media::{MediaReport, CONSUMER_ID, SYSTEM_ID};
+
+/// A 16 bits usage per report, as `MediaReport`.
+#[rustfmt::skip]
+const REPORT_DESCRIPTOR: &[u8] = &[
+    0x05, 0x0C,         // Usage Page (Consumer)
+    0x09, 0x01,         // Usage (Consumer Control)
+    0xA1, 0x01,         // Collection (Application)
+    0x85, CONSUMER_ID,  //   Report ID
+    0x15, 0x00,         //   Logical Minimum (0)
+    0x26, 0xFF, 0x03,   //   Logical Maximum (0x3FF)
+    0x19, 0x00,         //   Usage Minimum (0)
+    0x2A, 0xFF, 0x03,   //   Usage Maximum (0x3FF)
+    0x75, 0x10,         //   Report Size (16)
+    0x95, 0x01,         //   Report Count (1)
+    0x81, 0x00,         //   Input (Data, Array, Absolute)
+    0xC0,               // End Collection
+    0x05, 0x01,         // Usage Page (Generic Desktop)
+    0x09, 0x80,         // Usage (System Control)
+    0xA1, 0x01,         // Collection (Application)
+    0x85, SYSTEM_ID,    //   Report ID
+    0x15, 0x01,         //   Logical Minimum (0x01)
+    0x26, 0xB7, 0x00,   //   Logical Maximum (0xB7)
+    0x19, 0x01,         //   Usage Minimum (0x01)
+    0x2A, 0xB7, 0x00,   //   Usage Maximum (0xB7)
+    0x75, 0x10,         //   Report Size (16)
+    0x95, 0x01,         //   Report Count (1)
+    0x81, 0x00,         //   Input (Data, Array, Absolute)
+    0xC0,               // End Collection
+];
+
+#[derive(Default)]
+pub struct Media {
+    consumer: [u8; 3],
+    system: [u8; 3],
+}
+
+impl Media {
+    /// Sets the report returned to the host when it asks for it.
+    pub fn set_report(&mut self, report: &MediaReport) {
+        match report {
+            MediaReport::Consumer(_) => self.consumer = report.as_bytes(),
+            MediaReport::System(_) => self.system = report.as_bytes(),
+        }
+    }
+}
+
+impl HidDevice for Media {
+    fn subclass(&self) -> Subclass {
+        Subclass::None
+    }
+
+    fn protocol(&self) -> Protocol {
+        Protocol::None
+    }
+
+    fn report_descriptor(&self) -> &[u8] {
+        REPORT_DESCRIPTOR
+    }
+
+    fn get_report(&mut self, report_type: ReportType, report_id: u8) -> Result<&[u8], ()> {
+        match (report_type, report_id) {
+            (ReportType::Input, CONSUMER_ID) => Ok(&self.consumer),
+            (ReportType::Input, SYSTEM_ID) => Ok(&self.system),
+            _ => Err(()),
+        }
+    }
+
+    fn set_report(
+        &mut self,
+        _report_type: ReportType,
+        _report_id: u8,
+        _data: &[u8],
+    ) -> Result<(), ()> {
+        Err(())
+    }
+}
@@ -0,0 +1,137 @@
+//! The HID interface of the NKRO report, along the boot keyboard one.
+//!
+//! A class of its own rather than a keyberon `HidClass`, whose interrupt
+//! endpoint only takes 8 bytes. It also watches the host select the boot
+//! or the report protocol on the boot keyboard interface.
+
+use stuff::nkro::NkroReport;
+use usb_device::class_prelude::*;
+use usb_device::control::{Recipient, Request, RequestType};
+use usb_device::Result;
+
+/// Size of the interrupt endpoint, the report taking 22 bytes.
+const MAX_PACKET_SIZE: u16 = 32;
+
+const HID_DESCRIPTOR: u8 = 0x21;
+const REPORT_DESCRIPTOR_TYPE: u8 = 0x22;
+const GET_REPORT: u8 = 0x01;
+const SET_IDLE: u8 = 0x0a;
+const SET_PROTOCOL: u8 = 0x0b;
+
+/// The modifiers, then a bit for each of the 168 first keycodes, as
+/// `NkroReport`.
+#[rustfmt::skip]
+const REPORT_DESCRIPTOR: &[u8] = &[
+    0x05, 0x01,             // Usage Page (Generic Desktop)
+    0x09, 0x06,             // Usage (Keyboard)
+    0xA1, 0x01,             // Collection (Application)
+    0x05, 0x07,             //   Usage Page (Keyboard)
+    0x19, 0xE0,             //   Usage Minimum (Left Control)
+    0x29, 0xE7,             //   Usage Maximum (Right GUI)
+    0x15, 0x00,             //   Logical Minimum (0)
+    0x25, 0x01,             //   Logical Maximum (1)
+    0x75, 0x01,             //   Report Size (1)
+    0x95, 0x08,             //   Report Count (8)
+    0x81, 0x02,             //   Input (Data, Variable, Absolute)
+    0x19, 0x00,             //   Usage Minimum (0)
+    0x29, 0xA7,             //   Usage Maximum (167)
+    0x95, 0xA8,             //   Report Count (168)
+    0x81, 0x02,             //   Input (Data, Variable, Absolute)
+    0xC0,                   // End Collection
+];
+
+pub struct Nkro<'a, B: UsbBus> {
+    interface: InterfaceNumber,
+    endpoint: EndpointIn<'a, B>,
+    report: NkroReport,
+    boot_protocol: bool,
+}
+
+impl<'a, B: UsbBus> Nkro<'a, B> {
+    pub fn new(alloc: &'a UsbBusAllocator<B>) -> Self {
+        Nkro {
+            interface: alloc.interface(),
+            endpoint: alloc.interrupt(MAX_PACKET_SIZE, 10),
+            report: NkroReport::default(),
+            boot_protocol: false,
+        }
+    }
+
+    /// Sets the report returned to the host when it asks for it.
+    /// Returns whether it changed.
+    pub fn set_report(&mut self, report: NkroReport) -> bool {
+        let changed = report != self.report;
+        self.report = report;
+        changed
+    }
+
+    pub fn write(&mut self, data: &[u8]) -> Result<usize> {
+        self.endpoint.write(data)
+    }
+
+    /// Whether the host selected the boot protocol on the boot keyboard
+    /// interface, until it selects the report one or resets the bus.
+    pub fn boot_protocol(&self) -> bool {
+        self.boot_protocol
+    }
+}
+
+impl<B: UsbBus> UsbClass<B> for Nkro<'_, B> {
+    fn get_configuration_descriptors(&self, writer: &mut DescriptorWriter) -> Result<()> {
+        writer.interface(self.interface, 0x03, 0x00, 0x00)?;
+        let len = REPORT_DESCRIPTOR.len() as u16;
+        writer.write(
+            HID_DESCRIPTOR,
+            &[
+                0x11, // bcdHID 1.11
+                0x01,
+                0x00, // no country code
+                0x01, // one class descriptor
+                REPORT_DESCRIPTOR_TYPE,
+                len as u8,
+                (len >> 8) as u8,
+            ],
+        )?;
+        writer.endpoint(&self.endpoint)
+    }
+
+    fn reset(&mut self) {
+        self.boot_protocol = false;
+    }
+
+    fn control_in(&mut self, xfer: ControlIn<B>) {
+        let req = xfer.request();
+        if req.recipient != Recipient::Interface || req.index != u8::from(self.interface) as u16 {
+            return;
+        }
+        match (req.request_type, req.request) {
+            (RequestType::Standard, Request::GET_DESCRIPTOR)
+                if (req.value >> 8) as u8 == REPORT_DESCRIPTOR_TYPE =>
+            {
+                xfer.accept_with_static(REPORT_DESCRIPTOR).ok();
+            }
+            (RequestType::Class, GET_REPORT) => {
+                xfer.accept_with(self.report.as_bytes()).ok();
+            }
+            _ => (),
+        }
+    }
+
+    fn control_out(&mut self, xfer: ControlOut<B>) {
+        let req = xfer.request();
+        if req.request_type != RequestType::Class || req.recipient != Recipient::Interface {
+            return;
+        }
+        if req.index != u8::from(self.interface) as u16 {
+            // Only the boot keyboard interface knows the boot protocol.
+            // Left to its class to accept.
+            if req.request == SET_PROTOCOL {
+                self.boot_protocol = req.value == 0;
+            }
+            return;
+        }
+        if req.request == SET_IDLE {
+            xfer.accept().ok();
+        }
+    }
+}
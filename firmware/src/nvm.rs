@@ -0,0 +1,89 @@
+//! The settings area of the internal flash, written through the NVM
+//! controller.
+
+use atsamd_hal::target_device::NVMCTRL;
+use core::ptr;
+use stuff::storage::{Error, Flash};
+
+/// Size of a store sector: 4 rows, so that a sector holds the settings
+/// and the dynamic macros, and still has room for a new macro.
+pub const SECTOR_SIZE: usize = 4 * ROW_SIZE;
+
+const ROW_SIZE: usize = 256;
+
+extern "C" {
+    // Defined in memory.x
+    static _settings_start: u32;
+    static _settings_end: u32;
+}
+
+pub struct Nvm {
+    nvmctrl: NVMCTRL,
+    start: usize,
+    end: usize,
+}
+
+impl Nvm {
+    pub fn new(nvmctrl: NVMCTRL) -> Self {
+        // Pages are written by an explicit command
+        nvmctrl.ctrlb.modify(|_, w| w.manw().set_bit());
+        let (start, end) = unsafe {
+            (
+                &_settings_start as *const u32 as usize,
+                &_settings_end as *const u32 as usize,
+            )
+        };
+        Nvm {
+            nvmctrl,
+            start,
+            end,
+        }
+    }
+
+    fn set_addr(&mut self, offset: usize) {
+        // ADDR is in 16 bit words
+        let addr = ((self.start + offset) / 2) as u32;
+        self.nvmctrl.addr.write(|w| unsafe { w.addr().bits(addr) });
+    }
+
+    fn wait(&mut self) -> Result<(), Error> {
+        while self.nvmctrl.intflag.read().ready().bit_is_clear() {}
+        let status = self.nvmctrl.status.read();
+        if status.proge().bit_is_set() || status.locke().bit_is_set() || status.nvme().bit_is_set() {
+            self.nvmctrl
+                .status
+                .write(|w| w.proge().set_bit().locke().set_bit().nvme().set_bit());
+            return Err(Error::Flash);
+        }
+        Ok(())
+    }
+}
+
+impl Flash for Nvm {
+    const ERASE_SIZE: usize = ROW_SIZE;
+
+    fn capacity(&self) -> usize {
+        self.end - self.start
+    }
+
+    fn read(&self, offset: usize) -> u32 {
+        unsafe { ptr::read_volatile((self.start + offset) as *const u32) }
+    }
+
+    fn write(&mut self, offset: usize, word: u32) -> Result<(), Error> {
+        // The page buffer is cleared to 0xff, so the other words of the
+        // page are left untouched.
+        self.nvmctrl.ctrla.write(|w| w.cmdex().key().cmd().pbc());
+        self.wait()?;
+        unsafe { ptr::write_volatile((self.start + offset) as *mut u32, word) };
+        self.set_addr(offset);
+        self.nvmctrl.ctrla.write(|w| w.cmdex().key().cmd().wp());
+        self.wait()
+    }
+
+    fn erase(&mut self, offset: usize) -> Result<(), Error> {
+        self.set_addr(offset);
+        self.nvmctrl.ctrla.write(|w| w.cmdex().key().cmd().er());
+        self.wait()
+    }
+}
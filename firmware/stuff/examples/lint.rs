@@ -0,0 +1,27 @@
+//! Checks the keymap, printing the problems found.
+//!
+//! Exits with an error if any of them is an error.
+
+use stuff::dimensions::KEYS;
+use stuff::layers::{BASE_LAYERS, COMBOS, CONDITIONAL_LAYERS, LAYERS};
+use stuff::lint::{lint, Severity};
+
+fn main() {
+    let mut errors = 0;
+    lint(
+        LAYERS,
+        BASE_LAYERS,
+        COMBOS,
+        CONDITIONAL_LAYERS,
+        &KEYS,
+        |l| {
+            if l.severity() == Severity::Error {
+                errors += 1;
+            }
+            println!("{}: {}", l.severity(), l);
+        },
+    );
+    if errors > 0 {
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,30 @@
+//! Renders the keymap as cheat sheets in the given directory: one SVG
+//! per layer, a printable HTML page and a text version for the README.
+
+use std::fs;
+use std::path::PathBuf;
+use stuff::dimensions::GEOMETRY;
+use stuff::layers::LAYERS;
+use stuff::render::{ascii, html, svg};
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = PathBuf::from(std::env::args().nth(1).unwrap_or_else(|| "keymap".into()));
+    fs::create_dir_all(&dir)?;
+
+    let mut text = String::new();
+    for layer in 0..LAYERS.len() {
+        let mut out = String::new();
+        svg(LAYERS, layer, &GEOMETRY, &mut out)?;
+        fs::write(dir.join(format!("layer-{}.svg", layer)), out)?;
+        ascii(LAYERS, layer, &GEOMETRY, &mut text)?;
+        text.push('\n');
+    }
+    fs::write(dir.join("keymap.txt"), &text)?;
+
+    let mut out = String::new();
+    html(LAYERS, &GEOMETRY, &mut out)?;
+    fs::write(dir.join("keymap.html"), out)?;
+
+    print!("{}", text);
+    Ok(())
+}
@@ -0,0 +1,176 @@
+//! The actions of the keymap, keyberon's and ours.
+
+use crate::media::MediaKey;
+use crate::mouse::MouseAction;
+use crate::os::Os;
+use crate::sequence_macro::SequenceMacro;
+use crate::unicode::InputMethod;
+use keyberon::key_code::KeyCode;
+
+pub type Action = keyberon::action::Action<CustomAction>;
+pub type Layers = keyberon::layout::Layers<CustomAction>;
+
+/// The actions keyberon doesn't have, as `Action::Custom`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CustomAction {
+    HoldTap(&'static HoldTap),
+    TapDance(&'static TapDance),
+    OneShot(&'static OneShot),
+    /// Starts a sequence of the leader key.
+    Leader,
+    CapsWord(&'static CapsWord),
+    /// Toggles auto-shift.
+    AutoShift,
+    /// Toggles a layer, active as if its key was held.
+    ToggleLayer(usize),
+    /// Keeps the layers held active once their keys are released, or
+    /// releases the layers kept so.
+    LayerLock,
+    /// Switches the host OS profile.
+    Os(Os),
+    /// Records a dynamic macro in a slot, or stops recording.
+    RecordMacro(usize),
+    /// Stops recording a dynamic macro.
+    StopMacro,
+    /// Plays the dynamic macro of a slot.
+    PlayMacro(usize),
+    SequenceMacro(&'static SequenceMacro),
+    /// Types a character with the Unicode input method.
+    Unicode(char),
+    /// Switches the Unicode input method.
+    UnicodeInput(InputMethod),
+    Mouse(MouseAction),
+    /// Sends a consumer or system control usage while held.
+    Media(MediaKey),
+    /// Toggles between the 6KRO boot report and the NKRO report.
+    Nkro,
+    /// Enters the bootloader, once all the keys are released.
+    Bootloader,
+    /// Resets the keyboard, once all the keys are released.
+    Reset,
+}
+
+/// A key doing one action when tapped and another when held, such as
+/// a home-row modifier.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HoldTap {
+    pub hold: Action,
+    pub tap: Action,
+    /// Time after which the key is held, in ms. 0 for the tapping term
+    /// of the settings.
+    pub timeout: u16,
+    /// Held when another key is pressed and released while it is held.
+    pub permissive_hold: bool,
+    /// Held as soon as another key is pressed.
+    pub hold_on_other_key_press: bool,
+    /// Tapped right away when pressed less than this many ms after the
+    /// previous key press, as when typing fast. 0 to disable.
+    pub prior_idle: u16,
+}
+
+impl HoldTap {
+    /// A hold-tap with the tapping term of the settings, held only on
+    /// timeout.
+    pub const fn new(hold: Action, tap: Action) -> Self {
+        HoldTap {
+            hold,
+            tap,
+            timeout: 0,
+            permissive_hold: false,
+            hold_on_other_key_press: false,
+            prior_idle: 0,
+        }
+    }
+
+    /// A home-row modifier: `kc` when tapped, `modifier` when held, with
+    /// the heuristics that avoid misfires when typing.
+    pub const fn home_row_mod(modifier: KeyCode, kc: KeyCode) -> Self {
+        HoldTap {
+            permissive_hold: true,
+            prior_idle: 150,
+            ..Self::new(Action::KeyCode(modifier), Action::KeyCode(kc))
+        }
+    }
+}
+
+/// A key doing an action depending on how many times it is tapped, and
+/// whether the last tap is held.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TapDance {
+    /// Actions tapped after 1, 2... taps. More taps do the last one.
+    pub taps: &'static [Action],
+    /// Actions held when the last tap is held, after 1, 2... taps. The
+    /// tap action is held when missing.
+    pub holds: &'static [Action],
+    /// Time after which the dance ends if the key isn't tapped again, in
+    /// ms. 0 for the tapping term of the settings.
+    pub timeout: u16,
+}
+
+impl TapDance {
+    /// The action of a dance of `count` taps.
+    pub fn action(&self, count: usize, held: bool) -> &Action {
+        let n = count.max(1) - 1;
+        let tap = self.taps.get(n).or_else(|| self.taps.last());
+        let hold = if held { self.holds.get(n) } else { None };
+        hold.or(tap).unwrap_or(&Action::NoOp)
+    }
+}
+
+/// A modifier or layer key that, when tapped, applies to the next key
+/// press only.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OneShot {
+    pub action: Action,
+    /// Time after which the tapped key is cancelled if no other key is
+    /// pressed, in ms.
+    pub timeout: u16,
+}
+
+impl OneShot {
+    /// A one-shot key with a timeout of 1 s.
+    pub const fn new(action: Action) -> Self {
+        OneShot {
+            action,
+            timeout: 1000,
+        }
+    }
+}
+
+/// Shifts the letters typed, and turns `-` into `_`, until a key that is
+/// not part of a word, as when typing `CONSTANT_NAMES`. Pressed again, it
+/// stops.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CapsWord {
+    /// Keys that are part of a word besides letters and `-`, typed
+    /// unshifted.
+    pub continue_keys: &'static [KeyCode],
+}
+
+impl Default for CapsWord {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CapsWord {
+    /// Caps word continued by the digits, Backspace and Delete.
+    pub const fn new() -> Self {
+        use KeyCode::*;
+        CapsWord {
+            continue_keys: &[
+                Kb1, Kb2, Kb3, Kb4, Kb5, Kb6, Kb7, Kb8, Kb9, Kb0, BSpace, Delete,
+            ],
+        }
+    }
+
+    /// Whether `kc` is shifted.
+    pub fn shifts(&self, kc: KeyCode) -> bool {
+        (KeyCode::A..=KeyCode::Z).contains(&kc) || kc == KeyCode::Minus
+    }
+
+    /// Whether `kc` is part of a word.
+    pub fn continues(&self, kc: KeyCode) -> bool {
+        self.shifts(kc) || self.continue_keys.contains(&kc)
+    }
+}
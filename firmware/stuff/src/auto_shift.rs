@@ -0,0 +1,151 @@
+//! Auto-shift: keys held a little longer are typed shifted.
+//!
+//! The letters, digits and symbols of the layers wait for their release,
+//! another key press, or the timeout. Held past the timeout, the key is
+//! pressed with shift, and stays so until released, so that the host
+//! repeats the shifted key. Otherwise it is pressed as usual. The keys of
+//! hold-taps, tap-dances and combos are never auto-shifted.
+
+use keyberon::key_code::KeyCode;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AutoShift {
+    /// Time after which the key is shifted, in ms.
+    pub timeout: u16,
+    /// Keys never auto-shifted.
+    pub opt_out: &'static [KeyCode],
+}
+
+impl AutoShift {
+    /// Auto-shift with a timeout of 175 ms.
+    pub const fn new() -> Self {
+        AutoShift {
+            timeout: 175,
+            opt_out: &[],
+        }
+    }
+
+    /// Whether `kc` is auto-shifted.
+    pub fn shifts(&self, kc: KeyCode) -> bool {
+        let typed = (KeyCode::A..=KeyCode::Kb0).contains(&kc)
+            || (KeyCode::Minus..=KeyCode::Slash).contains(&kc);
+        typed && !self.opt_out.contains(&kc)
+    }
+}
+
+impl Default for AutoShift {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::action::{CustomAction, Layers};
+    use crate::scenario::Scenario;
+    use crate::settings::{features, Settings};
+    use keyberon::action::Action::*;
+    use keyberon::key_code::KeyCode::*;
+    use keyberon_macros::layout;
+
+    static LAYERS: Layers = layout! {
+        { [A Kb1 B Enter] [LShift {Custom(CustomAction::AutoShift)} '[' Space] }
+    };
+
+    static AUTO_SHIFT: AutoShift = AutoShift {
+        opt_out: &[B],
+        ..AutoShift::new()
+    };
+
+    fn scenario() -> Scenario {
+        let settings = Settings {
+            features: features::AUTO_SHIFT,
+            ..Settings::default()
+        };
+        Scenario::with_settings(LAYERS, &settings).auto_shift(&AUTO_SHIFT)
+    }
+
+    #[test]
+    fn test_tap() {
+        scenario()
+            .press(0, 0)
+            .tick(100)
+            .release(0, 0)
+            .expect(&[A])
+            .tick(1)
+            .expect(&[]);
+    }
+
+    #[test]
+    fn test_hold() {
+        scenario()
+            .press(0, 1)
+            .tick(174)
+            .expect(&[])
+            .tick(1)
+            .expect(&[Kb1, LShift])
+            // Held, for the host to repeat it
+            .tick(500)
+            .expect(&[Kb1, LShift])
+            .release(0, 1)
+            .expect(&[]);
+        // A symbol
+        scenario().press(1, 2).tick(200).expect(&[LBracket, LShift]);
+    }
+
+    #[test]
+    fn test_other_key_press() {
+        // Rolled: not shifted
+        scenario()
+            .press(0, 0)
+            .press(0, 1)
+            .expect(&[A])
+            .release(0, 0)
+            .tick(200)
+            .expect(&[Kb1, LShift]);
+        // Pressed once the other is shifted: not shifted either
+        scenario()
+            .press(0, 0)
+            .tick(200)
+            .expect(&[A, LShift])
+            .tap(0, 1)
+            .expect(&[A, Kb1]);
+    }
+
+    #[test]
+    fn test_not_shifted() {
+        // Opted out
+        scenario().press(0, 2).expect(&[B]).tick(200).expect(&[B]);
+        // or not typing
+        scenario()
+            .press(0, 3)
+            .expect(&[Enter])
+            .press(1, 3)
+            .tick(200)
+            .expect(&[Enter, Space]);
+    }
+
+    #[test]
+    fn test_toggle() {
+        let disabled = scenario().tap(1, 1);
+        // Saved in the settings
+        assert_eq!(disabled.keyboard().features(), 0);
+        disabled
+            .press(0, 0)
+            .tick(200)
+            .expect(&[A])
+            .release(0, 0)
+            .tap(1, 1)
+            .press(0, 0)
+            .tick(200)
+            .expect(&[A, LShift]);
+        assert_eq!(scenario().keyboard().features(), features::AUTO_SHIFT);
+        // Disabled in the settings
+        Scenario::new(LAYERS)
+            .auto_shift(&AUTO_SHIFT)
+            .press(0, 0)
+            .tick(200)
+            .expect(&[A]);
+    }
+}
@@ -0,0 +1,339 @@
+//! Combos: keys pressed together doing another action.
+//!
+//! The presses of the keys of a combo are held back until the combo is
+//! complete, another key is pressed or one of them is released, or the
+//! timeout of the combo expires. A complete combo presses a virtual key
+//! of `COMBO_ROW`, whose action is the one of the combo, and releases it
+//! with the first of its keys. Otherwise, the held back events are let
+//! through as they came.
+
+use crate::action::Action;
+use arrayvec::ArrayVec;
+use keyberon::layout::Event;
+
+/// Row of the virtual keys of the combos, the column being the index of
+/// the combo.
+pub const COMBO_ROW: u8 = u8::MAX;
+
+/// Maximum number of key presses held back.
+const MAX_PENDING: usize = 8;
+
+/// Maximum number of combos pressed at once.
+const MAX_ACTIVE: usize = 4;
+
+/// Events let through by the combos, with the time they came.
+pub(crate) type Events = ArrayVec<(Event, u32), { MAX_PENDING + 1 }>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Combo {
+    pub keys: &'static [(u8, u8)],
+    pub action: Action,
+    /// Time within which all the keys must be pressed, in ms.
+    pub timeout: u16,
+    /// Bit set of the layers where the combo is enabled, the layer being
+    /// the one when its first key is pressed.
+    pub layers: u32,
+}
+
+impl Combo {
+    /// A combo enabled on all the layers, with a timeout of 30 ms.
+    pub const fn new(keys: &'static [(u8, u8)], action: Action) -> Self {
+        Combo {
+            keys,
+            action,
+            timeout: 30,
+            layers: u32::MAX,
+        }
+    }
+
+    /// Whether the combo is enabled on `layer`.
+    pub fn enabled(&self, layer: usize) -> bool {
+        layer < 32 && self.layers & 1 << layer != 0
+    }
+
+    fn contains(&self, coord: (u8, u8)) -> bool {
+        self.keys.contains(&coord)
+    }
+
+    /// Bit set of all the keys of the combo.
+    fn mask_all(&self) -> u32 {
+        (1 << self.keys.len()) - 1
+    }
+
+    /// Bit set of the keys of the combo, for held back keys `pending`.
+    fn mask(&self, pending: &[((u8, u8), u32)]) -> u32 {
+        pending
+            .iter()
+            .filter_map(|(c, _)| self.keys.iter().position(|k| k == c))
+            .fold(0, |mask, i| mask | 1 << i)
+    }
+}
+
+/// A combo pressed, with the bit set of its keys still held.
+#[derive(Clone, Copy)]
+struct Active {
+    combo: usize,
+    held: u32,
+}
+
+pub(crate) struct Combos {
+    combos: &'static [Combo],
+    /// Key presses held back, with their time.
+    pending: ArrayVec<((u8, u8), u32), MAX_PENDING>,
+    /// Layer when the first held back key was pressed.
+    layer: usize,
+    active: ArrayVec<Active, MAX_ACTIVE>,
+}
+
+impl Combos {
+    pub fn new(combos: &'static [Combo]) -> Self {
+        Combos {
+            combos,
+            pending: ArrayVec::new(),
+            layer: 0,
+            active: ArrayVec::new(),
+        }
+    }
+
+    /// The action of the virtual key of a combo.
+    pub fn action(&self, combo: u8) -> &'static Action {
+        self.combos
+            .get(combo as usize)
+            .map_or(&Action::NoOp, |c| &c.action)
+    }
+
+    pub fn event(&mut self, event: Event, layer: usize, time: u32, out: &mut Events) {
+        match event {
+            Event::Press(i, j) => {
+                if self.pending.is_empty() {
+                    if !self
+                        .combos
+                        .iter()
+                        .any(|c| c.enabled(layer) && c.contains((i, j)))
+                    {
+                        out.push((event, time));
+                        return;
+                    }
+                    self.layer = layer;
+                } else if self.pending.is_full() {
+                    self.resolve(out);
+                }
+                self.pending.push(((i, j), time));
+                if self
+                    .candidates()
+                    .all(|c| c.keys.len() == self.pending.len())
+                {
+                    // Complete, or no combo left
+                    self.resolve(out);
+                }
+            }
+            Event::Release(i, j) => {
+                if self.pending.iter().any(|(c, _)| *c == (i, j)) {
+                    self.resolve(out);
+                }
+                let combos = self.combos;
+                let active = self.active.iter_mut().enumerate().find_map(|(a, active)| {
+                    let key = combos[active.combo]
+                        .keys
+                        .iter()
+                        .position(|&k| k == (i, j))?;
+                    Some((a, active, 1 << key))
+                });
+                match active {
+                    Some((a, active, key)) if active.held & key != 0 => {
+                        if active.held == combos[active.combo].mask_all() {
+                            out.push((Event::Release(COMBO_ROW, active.combo as u8), time));
+                        }
+                        active.held &= !key;
+                        if active.held == 0 {
+                            self.active.remove(a);
+                        }
+                    }
+                    _ => out.push((event, time)),
+                }
+            }
+        }
+    }
+
+    /// Lets through the held back keys whose combos have timed out.
+    pub fn tick(&mut self, time: u32, out: &mut Events) {
+        if let Some(&(_, since)) = self.pending.first() {
+            let timeout = self.candidates().map(|c| c.timeout).max().unwrap_or(0);
+            if time.wrapping_sub(since) >= timeout as u32 {
+                self.resolve(out);
+            }
+        }
+    }
+
+    /// The enabled combos that the held back keys could still complete.
+    fn candidates(&self) -> impl Iterator<Item = &'static Combo> + '_ {
+        let pending = &self.pending;
+        let layer = self.layer;
+        self.combos.iter().filter(move |c| {
+            c.enabled(layer) && pending.iter().all(|(coord, _)| c.contains(*coord))
+        })
+    }
+
+    /// Presses the largest combo complete among the held back keys, as
+    /// when its first key was pressed, and lets the others through.
+    fn resolve(&mut self, out: &mut Events) {
+        let pending = &self.pending;
+        let layer = self.layer;
+        let complete = self
+            .combos
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| c.enabled(layer) && c.mask(pending) == c.mask_all())
+            .max_by_key(|(i, c)| (c.keys.len(), usize::MAX - i));
+        if let Some((i, combo)) = complete {
+            if self
+                .active
+                .try_push(Active {
+                    combo: i,
+                    held: combo.mask_all(),
+                })
+                .is_ok()
+            {
+                let since = pending
+                    .iter()
+                    .find(|(c, _)| combo.contains(*c))
+                    .map_or(0, |&(_, time)| time);
+                out.push((Event::Press(COMBO_ROW, i as u8), since));
+                self.pending.retain(|(c, _)| !combo.contains(*c));
+            }
+        }
+        for (c, time) in self.pending.drain(..) {
+            out.push((Event::Press(c.0, c.1), time));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::action::Layers;
+    use crate::scenario::Scenario;
+    use keyberon::action::Action::*;
+    use keyberon::key_code::KeyCode::*;
+    use keyberon_macros::layout;
+
+    static LAYERS: Layers = layout! {
+        { [A B C D] [(1) E F G] }
+        { [Kb1 Kb2 Kb3 Kb4] [t Kb5 t t] }
+    };
+
+    static COMBOS: &[Combo] = &[
+        Combo::new(&[(0, 0), (0, 1)], KeyCode(Escape)),
+        Combo {
+            timeout: 50,
+            ..Combo::new(&[(0, 0), (0, 1), (0, 2)], KeyCode(Enter))
+        },
+        Combo {
+            layers: 1 << 0,
+            ..Combo::new(&[(0, 2), (0, 3)], Layer(1))
+        },
+    ];
+
+    fn scenario() -> Scenario {
+        Scenario::new(LAYERS).combos(COMBOS)
+    }
+
+    #[test]
+    fn test_combo() {
+        scenario()
+            .press(0, 2)
+            .press(0, 3)
+            .tick(1)
+            .press(1, 1)
+            .tick(1)
+            .expect(&[Kb5])
+            .release(1, 1)
+            .release(0, 2)
+            .release(0, 3)
+            .tick(1)
+            .press(1, 1)
+            .tick(1)
+            .expect(&[E]);
+    }
+
+    #[test]
+    fn test_single_keys_wait() {
+        scenario()
+            .press(0, 0)
+            .expect(&[])
+            .tick(30)
+            .expect(&[])
+            // Waits for the longest combo
+            .tick(19)
+            .expect(&[])
+            .tick(1)
+            .expect(&[A])
+            .release(0, 0)
+            .tick(1)
+            .expect(&[]);
+        // Let through as soon as another key is pressed
+        scenario().press(0, 2).press(1, 1).tick(1).expect(&[C, E]);
+        // or released
+        scenario()
+            .press(0, 3)
+            .release(0, 3)
+            .expect(&[D])
+            .tick(1)
+            .expect(&[]);
+    }
+
+    #[test]
+    fn test_overlapping() {
+        // The smaller combo on timeout
+        scenario()
+            .press(0, 0)
+            .press(0, 1)
+            .tick(48)
+            .expect(&[])
+            .tick(1)
+            .expect(&[Escape]);
+        // the larger one when complete
+        scenario()
+            .press(0, 0)
+            .press(0, 1)
+            .press(0, 2)
+            .expect(&[Enter]);
+        // the smaller one and a key when another key is pressed
+        scenario()
+            .press(0, 1)
+            .press(0, 0)
+            .press(0, 3)
+            .expect(&[Escape, D]);
+    }
+
+    #[test]
+    fn test_partial_release() {
+        scenario()
+            .press(0, 2)
+            .press(0, 0)
+            .press(0, 1)
+            .expect(&[Enter])
+            .release(0, 1)
+            .expect(&[])
+            // The other keys of the combo do nothing
+            .release(0, 0)
+            .press(1, 1)
+            .tick(1)
+            .expect(&[E])
+            .release(0, 2)
+            .release(1, 1)
+            .tick(1)
+            .expect(&[]);
+    }
+
+    #[test]
+    fn test_per_layer() {
+        // The layer combo is disabled on layer 1
+        scenario()
+            .press(1, 0)
+            .press(0, 2)
+            .press(0, 3)
+            .tick(1)
+            .expect(&[Kb3, Kb4]);
+    }
+}
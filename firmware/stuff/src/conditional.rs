@@ -0,0 +1,93 @@
+//! Conditional layers: a layer activated when some others are all held,
+//! as the tri-layer of other firmwares.
+//!
+//! The layers held are the ones of the layer keys pressed, whatever the
+//! layer they are pressed on. The first conditional layer whose layers
+//! are all held is the current layer. Otherwise, the layers held add up
+//! as usual.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConditionalLayer {
+    /// Layers that must all be held.
+    pub if_all: &'static [usize],
+    pub then: usize,
+}
+
+impl ConditionalLayer {
+    /// Whether `held`, a bit set of layers, activates the layer.
+    pub fn matches(&self, held: u32) -> bool {
+        !self.if_all.is_empty() && self.if_all.iter().all(|&l| held & bit(l) != 0)
+    }
+}
+
+/// The bit of `layer` in a bit set of layers, none past 31.
+pub fn bit(layer: usize) -> u32 {
+    1u32.checked_shl(layer as u32).unwrap_or(0)
+}
+
+/// The layer activated by `held`, a bit set of layers, if any.
+pub fn resolve(conditionals: &[ConditionalLayer], held: u32) -> Option<usize> {
+    conditionals
+        .iter()
+        .find(|c| c.matches(held))
+        .map(|c| c.then)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::action::Layers;
+    use crate::scenario::Scenario;
+    use keyberon::key_code::KeyCode::*;
+    use keyberon_macros::layout;
+
+    static LAYERS: Layers = layout! {
+        { [A (1) (2)] }
+        { [Kb1 t t] }
+        { [Kb2 t t] }
+        { [Kb3 t t] }
+        { [Kb4 t t] }
+    };
+
+    static CONDITIONALS: &[ConditionalLayer] = &[ConditionalLayer {
+        if_all: &[1, 2],
+        then: 4,
+    }];
+
+    fn scenario() -> Scenario {
+        Scenario::new(LAYERS).conditional_layers(CONDITIONALS)
+    }
+
+    #[test]
+    fn test_resolve() {
+        assert_eq!(resolve(CONDITIONALS, 0b110), Some(4));
+        assert_eq!(resolve(CONDITIONALS, 0b1110), Some(4));
+        assert_eq!(resolve(CONDITIONALS, 0b10), None);
+        assert_eq!(resolve(&[], 0b110), None);
+    }
+
+    #[test]
+    fn test_conditional_layer() {
+        scenario()
+            .press(0, 1)
+            .press(0, 2)
+            .press(0, 0)
+            .expect(&[Kb4])
+            .release(0, 0)
+            .release(0, 1)
+            .press(0, 0)
+            .expect(&[Kb2]);
+        // In any order
+        scenario()
+            .press(0, 2)
+            .press(0, 1)
+            .press(0, 0)
+            .expect(&[Kb4]);
+        // Otherwise, the layers add up
+        Scenario::new(LAYERS)
+            .press(0, 1)
+            .press(0, 2)
+            .press(0, 0)
+            .expect(&[Kb3]);
+    }
+}
@@ -1,2 +1,55 @@
 pub const ROWS: usize = 4;
 pub const COLS: usize = 7;
+
+/// Positions of the layout where there is a key, the two halves side
+/// by side.
+#[rustfmt::skip]
+pub const KEYS: [[bool; 2 * COLS]; ROWS] = {
+    const O: bool = true;
+    const X: bool = false;
+    [
+        [O, O, O, O, O, O, X,    X, O, O, O, O, O, O],
+        [X, O, O, O, O, O, O,    X, O, O, O, O, O, O],
+        [X, O, O, O, O, O, O,    O, O, O, O, O, O, O],
+        [X, X, X, O, O, O, O,    O, O, O, O, X, X, X],
+    ]
+};
+
+/// Physical placement of the keys of a layout.
+pub struct Geometry<'a, const W: usize> {
+    /// Positions of the layout where there is a key.
+    pub keys: &'a [[bool; W]],
+    /// Vertical offset of each column, in quarters of a key.
+    pub stagger: [u8; W],
+    /// First column of the right half.
+    pub split: usize,
+}
+
+impl<'a, const W: usize> Geometry<'a, W> {
+    /// Gap between the two halves, in quarters of a key.
+    pub const GAP: u16 = 6;
+
+    /// Position of the key at `(i, j)`, in quarters of a key, if any.
+    pub fn position(&self, i: usize, j: usize) -> Option<(u16, u16)> {
+        if !*self.keys.get(i)?.get(j)? {
+            return None;
+        }
+        let gap = if j >= self.split { Self::GAP } else { 0 };
+        Some((j as u16 * 4 + gap, i as u16 * 4 + self.stagger[j] as u16))
+    }
+
+    /// Size of the layout, in quarters of a key.
+    pub fn size(&self) -> (u16, u16) {
+        let stagger = self.stagger.iter().max().copied().unwrap_or(0) as u16;
+        (
+            W as u16 * 4 + Self::GAP,
+            self.keys.len() as u16 * 4 + stagger,
+        )
+    }
+}
+
+pub const GEOMETRY: Geometry<'static, { 2 * COLS }> = Geometry {
+    keys: &KEYS,
+    stagger: [3, 3, 1, 0, 1, 2, 2, 2, 2, 1, 0, 1, 3, 3],
+    split: COLS,
+};
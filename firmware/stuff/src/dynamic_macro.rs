@@ -0,0 +1,194 @@
+//! Dynamic macros: keystrokes recorded on the keyboard, and played back
+//! with their timing.
+//!
+//! While recording, the changes of the keycodes are recorded as steps,
+//! each with the time since the previous one. Playing a macro presses
+//! and releases its keycodes at the same pace, along with the keys
+//! pressed meanwhile. The macros are kept in the flash store, one record
+//! per slot.
+
+use crate::settings::Key;
+use crate::storage::{Error, Flash, Store, MAX_LEN};
+use arrayvec::ArrayVec;
+use keyberon::key_code::KeyCode;
+
+/// Number of macro slots.
+pub const SLOTS: usize = 3;
+
+/// Maximum number of steps of a macro, as a press and a release make
+/// two steps. Three slots fit in a sector of the store with the
+/// settings.
+pub const MAX_STEPS: usize = 64;
+
+/// Size of an encoded step, in bytes.
+const STEP_LEN: usize = 3;
+
+/// Maximum time between two steps, in ms. Longer pauses are shortened.
+const MAX_DELAY: u16 = 0x7fff;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Step {
+    /// Time since the previous step, in ms.
+    pub delay: u16,
+    pub keycode: KeyCode,
+    pub pressed: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Macro {
+    steps: ArrayVec<Step, MAX_STEPS>,
+}
+
+impl Macro {
+    pub fn steps(&self) -> &[Step] {
+        &self.steps
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.steps.is_full()
+    }
+
+    /// Adds a step, unless the macro is full.
+    pub fn push(&mut self, delay: u32, keycode: KeyCode, pressed: bool) {
+        let _ = self.steps.try_push(Step {
+            delay: delay.min(MAX_DELAY as u32) as u16,
+            keycode,
+            pressed,
+        });
+    }
+
+    /// The steps as bytes: the keycode, then the delay with the pressed
+    /// flag as its high bit, little endian.
+    pub fn encode(&self) -> ArrayVec<u8, { MAX_STEPS * STEP_LEN }> {
+        let mut bytes = ArrayVec::new();
+        for step in &self.steps {
+            let word = step.delay | (step.pressed as u16) << 15;
+            bytes.push(step.keycode as u8);
+            bytes.extend(word.to_le_bytes());
+        }
+        bytes
+    }
+
+    /// The macro encoded as `bytes`, none if they are not a macro.
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        if !bytes.len().is_multiple_of(STEP_LEN) || bytes.len() > MAX_STEPS * STEP_LEN {
+            return None;
+        }
+        let mut steps = ArrayVec::new();
+        for step in bytes.chunks(STEP_LEN) {
+            let word = u16::from_le_bytes([step[1], step[2]]);
+            steps.push(Step {
+                delay: word & MAX_DELAY,
+                keycode: keycode(step[0])?,
+                pressed: word >> 15 != 0,
+            });
+        }
+        Some(Macro { steps })
+    }
+
+    /// Loads the macro of `slot`, empty if missing.
+    pub fn load<F: Flash>(store: &Store<F>, slot: usize) -> Self {
+        let mut buf = [0; MAX_LEN];
+        store
+            .read(key(slot), &mut buf)
+            .and_then(|len| Self::decode(buf.get(..len)?))
+            .unwrap_or_default()
+    }
+
+    pub fn save<F: Flash>(&self, store: &mut Store<F>, slot: usize) -> Result<(), Error> {
+        store.write(key(slot), &self.encode())
+    }
+}
+
+/// Store key of `slot`.
+fn key(slot: usize) -> u8 {
+    Key::Macros as u8 + slot as u8
+}
+
+/// The keycode of value `n`, if any.
+fn keycode(n: u8) -> Option<KeyCode> {
+    use KeyCode::*;
+    if n <= ExSel as u8 || (LCtrl as u8..=RGui as u8).contains(&n) {
+        // Safe as `KeyCode` is `repr(u8)`, with no gaps in these ranges.
+        Some(unsafe { core::mem::transmute::<u8, KeyCode>(n) })
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::action::{CustomAction, Layers};
+    use crate::scenario::Scenario;
+    use keyberon::action::Action::*;
+    use keyberon::key_code::KeyCode::*;
+    use keyberon_macros::layout;
+
+    static LAYERS: Layers = layout! {
+        {
+            [{Custom(CustomAction::RecordMacro(0))} {Custom(CustomAction::StopMacro)} {Custom(CustomAction::PlayMacro(0))} (1)]
+            [LShift A B n]
+        }
+        { [t t t t] [t Kb1 t n] }
+    };
+
+    #[test]
+    fn test_encode() {
+        let mut m = Macro::default();
+        m.push(0, LShift, true);
+        m.push(40_000, A, true);
+        m.push(12, A, false);
+        assert_eq!(m.steps()[1].delay, MAX_DELAY);
+        let bytes = m.encode();
+        assert_eq!(&bytes[6..], &[A as u8, 12, 0]);
+        assert_eq!(Macro::decode(&bytes), Some(m));
+        assert_eq!(Macro::decode(&[A as u8, 0]), None);
+        assert_eq!(Macro::decode(&[0xd0, 0, 0]), None);
+        assert_eq!(keycode(RGui as u8), Some(RGui));
+    }
+
+    #[test]
+    fn test_record_and_play() {
+        Scenario::new(LAYERS)
+            .tap(0, 0)
+            .press(1, 0)
+            .tap(1, 1)
+            .release(1, 0)
+            .tick(10)
+            .press(0, 3) // Layer 1
+            .tap(1, 1)
+            .release(0, 3)
+            .tap(0, 1)
+            .tick(100)
+            .press(0, 2)
+            .expect(&[LShift])
+            .tick(1)
+            .expect(&[LShift, A])
+            .tick(2)
+            .expect(&[])
+            .press(1, 2) // Along with the macro
+            .tick(10)
+            .expect(&[B])
+            .tick(1)
+            .expect(&[B, Kb1])
+            .tick(1)
+            .expect(&[B])
+            .release(1, 2)
+            .expect(&[]);
+    }
+
+    #[test]
+    fn test_record_again() {
+        // Recording again stops the recording, and no macro is played
+        // while recording.
+        Scenario::new(LAYERS)
+            .tap(0, 0)
+            .tap(1, 1)
+            .tap(0, 2)
+            .expect(&[])
+            .tap(0, 0)
+            .press(0, 2)
+            .expect(&[A]);
+    }
+}
@@ -0,0 +1,133 @@
+//! Key overrides: a key sending other keycodes while some modifiers are
+//! held, such as shift and backspace sending delete.
+//!
+//! The overrides apply to the keycodes of each tick, before they make
+//! the report. The first override of the current layer whose key and
+//! modifiers are all pressed replaces its key, and releases its
+//! modifiers for as long as it applies.
+
+use arrayvec::ArrayVec;
+use keyberon::key_code::KeyCode;
+
+/// Modifiers of `KeyOverride::mods`, left or right.
+pub mod mods {
+    pub const CTRL: u8 = 1 << 0;
+    pub const SHIFT: u8 = 1 << 1;
+    pub const ALT: u8 = 1 << 2;
+    pub const GUI: u8 = 1 << 3;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyOverride {
+    /// Bit set of the modifiers that must all be held.
+    pub mods: u8,
+    pub key: KeyCode,
+    /// Keycodes sent instead of the key and the modifiers.
+    pub replacement: &'static [KeyCode],
+    /// Bit set of the layers where the override is enabled.
+    pub layers: u32,
+}
+
+impl KeyOverride {
+    /// An override enabled on all the layers.
+    pub const fn new(mods: u8, key: KeyCode, replacement: &'static [KeyCode]) -> Self {
+        KeyOverride {
+            mods,
+            key,
+            replacement,
+            layers: u32::MAX,
+        }
+    }
+
+    /// Whether the override is enabled on `layer`.
+    pub fn enabled(&self, layer: usize) -> bool {
+        layer < 32 && self.layers & 1 << layer != 0
+    }
+}
+
+/// The bit of the modifier `kc` in `mods`, 0 if not a modifier.
+fn modifier(kc: KeyCode) -> u8 {
+    use KeyCode::*;
+    match kc {
+        LCtrl | RCtrl => mods::CTRL,
+        LShift | RShift => mods::SHIFT,
+        LAlt | RAlt => mods::ALT,
+        LGui | RGui => mods::GUI,
+        _ => 0,
+    }
+}
+
+/// Applies the first of `overrides` enabled on `layer` that matches
+/// `keycodes`.
+pub fn apply<const N: usize>(
+    overrides: &[KeyOverride],
+    layer: usize,
+    keycodes: &mut ArrayVec<KeyCode, N>,
+) {
+    let held = keycodes.iter().fold(0, |held, &kc| held | modifier(kc));
+    let matching = overrides.iter().find(|o| {
+        o.enabled(layer) && o.mods != 0 && held & o.mods == o.mods && keycodes.contains(&o.key)
+    });
+    if let Some(o) = matching {
+        keycodes.retain(|kc| *kc != o.key && modifier(*kc) & o.mods == 0);
+        for &kc in o.replacement {
+            let _ = keycodes.try_push(kc);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::action::Layers;
+    use crate::scenario::Scenario;
+    use keyberon::key_code::KeyCode::*;
+    use keyberon_macros::layout;
+
+    static LAYERS: Layers = layout! {
+        { [LShift BSpace Comma (1)] [RCtrl LAlt A n] }
+        { [t t t t] [t t B n] }
+    };
+
+    static OVERRIDES: &[KeyOverride] = &[
+        KeyOverride::new(mods::SHIFT, BSpace, &[Delete]),
+        KeyOverride {
+            layers: 1 << 0,
+            ..KeyOverride::new(mods::SHIFT, Comma, &[SColon])
+        },
+        KeyOverride::new(mods::CTRL | mods::ALT, A, &[LShift, Z]),
+    ];
+
+    fn apply(layer: usize, keycodes: &[KeyCode]) -> Vec<KeyCode> {
+        let mut keycodes: ArrayVec<KeyCode, 8> = keycodes.iter().copied().collect();
+        super::apply(OVERRIDES, layer, &mut keycodes);
+        keycodes.to_vec()
+    }
+
+    #[test]
+    fn test_apply() {
+        assert_eq!(apply(0, &[LShift, BSpace]), [Delete]);
+        assert_eq!(apply(0, &[RShift, LCtrl, BSpace]), [LCtrl, Delete]);
+        assert_eq!(apply(0, &[BSpace]), [BSpace]);
+        assert_eq!(apply(0, &[LShift, Comma]), [SColon]);
+        assert_eq!(apply(1, &[LShift, Comma]), [LShift, Comma]);
+        assert_eq!(apply(0, &[RCtrl, A]), [RCtrl, A]);
+        assert_eq!(apply(0, &[LAlt, RCtrl, A]), [LShift, Z]);
+    }
+
+    #[test]
+    fn test_key_override() {
+        Scenario::new(LAYERS)
+            .key_overrides(OVERRIDES)
+            .press(0, 0)
+            .expect(&[LShift])
+            .press(0, 1)
+            .expect(&[Delete])
+            .release(0, 1)
+            .expect(&[LShift])
+            .press(0, 2)
+            .expect(&[SColon])
+            .press(0, 3)
+            .expect(&[LShift, Comma]);
+    }
+}
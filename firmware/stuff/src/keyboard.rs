@@ -0,0 +1,677 @@
+//! The keyboard logic, from the key events to the HID reports.
+//!
+//! Keys act as with keyberon's `Layout`: the current layer is the sum of
+//! the layer keys held, toggled or locked, unless they activate a
+//! conditional layer, and `Trans` falls back to the default layer. The
+//! events go through the combos first.
+
+mod hold_tap;
+mod one_shot;
+mod report;
+mod typing;
+
+use crate::action::{Action, CapsWord, CustomAction, Layers};
+use crate::auto_shift::AutoShift;
+use crate::combo::{Combo, Combos, Events, COMBO_ROW};
+use crate::conditional::{self, bit, ConditionalLayer};
+use crate::dimensions::{COLS, ROWS};
+use crate::dynamic_macro::{Macro, SLOTS};
+use crate::key_override::KeyOverride;
+use crate::leader::{Leader, Trie};
+use crate::media::Media;
+use crate::mouse::{Mouse, MouseKeys};
+use crate::os::Os;
+use crate::sequence_macro::Typing;
+use crate::settings::{features, Settings};
+use crate::unicode::InputMethod;
+use arrayvec::ArrayVec;
+use hold_tap::{Dance, Waiting};
+use keyberon::key_code::KeyCode;
+use keyberon::layout::Event;
+use one_shot::{Leading, OneShotKey, MAX_ONE_SHOTS};
+use typing::{Playing, Recording, MAX_TYPED};
+
+/// Maximum number of keycodes pressed at once. The keycodes of the keys
+/// pressed past it are left out of the reports.
+pub const MAX_KEYCODES: usize = 32;
+
+/// Maximum number of events waiting for a key to be decided: a press and
+/// a release of each key. Hold-tap keys with permissive hold or hold on
+/// other key press, auto-shift and tap dances are decided before that.
+const QUEUE_LEN: usize = 2 * ROWS * 2 * COLS;
+
+/// Maximum number of actions pressed at once.
+const MAX_PRESSED: usize = 32;
+
+/// Maximum number of keys released at the next tick: the one-shot keys
+/// tapped, the dance key and the leader key.
+const MAX_TO_RELEASE: usize = MAX_ONE_SHOTS + 2;
+
+/// Time all the keys stay released before rebooting, so that the host
+/// reads their release, in ms.
+const REBOOT_DELAY_MS: u32 = 20;
+
+/// A reboot asked by a `Bootloader` or `Reset` key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reboot {
+    Bootloader,
+    Reset,
+}
+
+/// The events processed in a tick: they all press keys, or all release
+/// them, so that the host sees each key pressed or released in a report.
+#[derive(Default)]
+struct Batch(Option<bool>);
+
+impl Batch {
+    /// Whether an event pressing keys, or releasing them, goes in the
+    /// batch, that it then joins.
+    fn join(&mut self, press: bool) -> bool {
+        let joins = self.0 != Some(!press);
+        if joins {
+            self.0 = Some(press);
+        }
+        joins
+    }
+}
+
+/// A pressed key, with the action it resolved to.
+#[derive(Clone, Copy)]
+struct Pressed {
+    coord: (u8, u8),
+    action: &'static Action,
+    /// Shifted by caps word.
+    shifted: bool,
+}
+
+pub struct Keyboard {
+    layers: Layers,
+    combos: Combos,
+    conditionals: &'static [ConditionalLayer],
+    key_overrides: &'static [KeyOverride],
+    default_layer: usize,
+    base_layers: &'static [usize],
+    /// Bit set of the layers toggled or locked.
+    latched: u32,
+    tapping_term: u16,
+    /// Time in ms, counted in ticks.
+    time: u32,
+    /// The events waiting, with the time they came.
+    queue: ArrayVec<(Event, u32), QUEUE_LEN>,
+    pressed: ArrayVec<Pressed, MAX_PRESSED>,
+    waiting: Option<Waiting>,
+    dance: Option<Dance>,
+    one_shots: ArrayVec<OneShotKey, MAX_ONE_SHOTS>,
+    next_one_shot: u8,
+    leader: Trie,
+    leader_timeout: u16,
+    leading: Option<Leading>,
+    caps_word: Option<&'static CapsWord>,
+    auto_shift: Option<&'static AutoShift>,
+    auto_shift_enabled: bool,
+    /// Whether the keys go in the NKRO report, rather than the boot one.
+    nkro: bool,
+    /// Whether the host selected the boot protocol, which only knows the
+    /// boot report.
+    boot_protocol: bool,
+    os: Os,
+    macros: [Macro; SLOTS],
+    recording: Option<Recording>,
+    playing: Option<Playing>,
+    /// Slot of the macro recorded and not saved yet.
+    unsaved_macro: Option<usize>,
+    typing: Option<Typing>,
+    /// Actions to type once the current one is typed.
+    typed: ArrayVec<&'static Action, MAX_TYPED>,
+    unicode_input: InputMethod,
+    mouse: Mouse,
+    media: Media,
+    /// The reboot asked, with the time a key was last pressed.
+    reboot: Option<(Reboot, u32)>,
+    /// Whether the last report was sent to the host.
+    sent: bool,
+    /// Keys to release at the next tick.
+    to_release: ArrayVec<(u8, u8), MAX_TO_RELEASE>,
+    last_press: Option<u32>,
+    last_tap: Option<((u8, u8), u32)>,
+    keycodes: ArrayVec<KeyCode, MAX_KEYCODES>,
+}
+
+impl Keyboard {
+    pub fn new(layers: Layers, settings: &Settings) -> Self {
+        Keyboard {
+            layers,
+            combos: Combos::new(&[]),
+            conditionals: &[],
+            key_overrides: &[],
+            base_layers: &[],
+            latched: 0,
+            default_layer: (settings.default_layer as usize).min(layers.len() - 1),
+            tapping_term: settings.tapping_term,
+            time: 0,
+            queue: ArrayVec::new(),
+            pressed: ArrayVec::new(),
+            waiting: None,
+            dance: None,
+            one_shots: ArrayVec::new(),
+            next_one_shot: 0,
+            leader: Trie::new(&[]).0,
+            leader_timeout: 0,
+            leading: None,
+            caps_word: None,
+            auto_shift: None,
+            auto_shift_enabled: settings.features & features::AUTO_SHIFT != 0,
+            nkro: settings.features & features::NKRO != 0,
+            boot_protocol: false,
+            os: Os::from_u8(settings.os).unwrap_or_default(),
+            macros: Default::default(),
+            recording: None,
+            playing: None,
+            unsaved_macro: None,
+            typing: None,
+            typed: ArrayVec::new(),
+            unicode_input: InputMethod::from_u8(settings.unicode_input).unwrap_or_default(),
+            mouse: Mouse::new(MouseKeys::new()),
+            media: Media::default(),
+            reboot: None,
+            sent: true,
+            to_release: ArrayVec::new(),
+            last_press: None,
+            last_tap: None,
+            keycodes: ArrayVec::new(),
+        }
+    }
+
+    pub fn with_combos(mut self, combos: &'static [Combo]) -> Self {
+        self.combos = Combos::new(combos);
+        self
+    }
+
+    pub fn with_conditional_layers(mut self, conditionals: &'static [ConditionalLayer]) -> Self {
+        self.conditionals = conditionals;
+        self
+    }
+
+    /// Makes `base_layers` reachable only as the default layer: layers
+    /// held adding up to one of them give the default layer instead.
+    pub fn with_base_layers(mut self, base_layers: &'static [usize]) -> Self {
+        self.base_layers = base_layers;
+        self
+    }
+
+    pub fn with_key_overrides(mut self, key_overrides: &'static [KeyOverride]) -> Self {
+        self.key_overrides = key_overrides;
+        self
+    }
+
+    /// Uses the dynamic macros `macros`, as loaded from the store.
+    pub fn with_macros(mut self, macros: [Macro; SLOTS]) -> Self {
+        self.macros = macros;
+        self
+    }
+
+    /// Uses the sequences of `leader`. Those that don't fit, as reported by
+    /// `Trie::new`, are left out.
+    pub fn with_leader(mut self, leader: &'static Leader) -> Self {
+        self.leader = Trie::new(leader.sequences).0;
+        self.leader_timeout = leader.timeout;
+        self
+    }
+
+    /// Uses `auto_shift`, if enabled in the settings or toggled.
+    pub fn with_auto_shift(mut self, auto_shift: &'static AutoShift) -> Self {
+        self.auto_shift = Some(auto_shift);
+        self
+    }
+
+    /// Uses the acceleration and speeds of `mouse_keys` instead of the
+    /// default ones.
+    pub fn with_mouse_keys(mut self, mouse_keys: &'static MouseKeys) -> Self {
+        self.mouse = Mouse::new(*mouse_keys);
+        self
+    }
+
+    /// Queues an event, processed by the next ticks. It comes at the time
+    /// of the next tick, as the firmware scans the keys on each tick.
+    pub fn event(&mut self, event: Event) {
+        let time = self.time.wrapping_add(1);
+        let mut events = Events::new();
+        self.combos
+            .event(event, self.current_layer(), time, &mut events);
+        events.into_iter().for_each(|e| self.enqueue(e));
+    }
+
+    fn enqueue(&mut self, event: (Event, u32)) {
+        while self.queue.is_full() {
+            // Only a key waiting for its timeout alone gets there: it is
+            // held, as at its timeout.
+            self.hold_waiting();
+            self.step();
+        }
+        self.queue.push(event);
+    }
+
+    /// Advances the keyboard by one ms.
+    pub fn tick(&mut self) {
+        self.time = self.time.wrapping_add(1);
+        let mut events = Events::new();
+        self.combos.tick(self.time, &mut events);
+        events.into_iter().for_each(|e| self.enqueue(e));
+        self.expire_one_shots();
+        self.step();
+        self.expire_leader();
+        self.update_mouse_media();
+        if let Some((_, since)) = &mut self.reboot {
+            if !self.pressed.is_empty() {
+                *since = self.time;
+            }
+        }
+        self.update_keycodes();
+        self.record();
+        self.play();
+        self.type_sequence();
+    }
+
+    /// The reboot asked, as soon as its key is pressed.
+    pub fn reboot_pending(&self) -> Option<Reboot> {
+        self.reboot.map(|(reboot, _)| reboot)
+    }
+
+    /// The reboot to do now, once all the keys are released for a while.
+    pub fn reboot(&self) -> Option<Reboot> {
+        self.reboot
+            .filter(|&(_, since)| self.time.wrapping_sub(since) >= REBOOT_DELAY_MS)
+            .map(|(reboot, _)| reboot)
+    }
+
+    pub fn current_layer(&self) -> usize {
+        let mut held = 0;
+        let mut sum = None;
+        for l in self.held_layers() {
+            held |= bit(l);
+            sum = Some(sum.unwrap_or(0) + l);
+        }
+        for l in (0..32).filter(|&l| self.latched & !held & bit(l) != 0) {
+            sum = Some(sum.unwrap_or(0) + l);
+        }
+        conditional::resolve(self.conditionals, held | self.latched)
+            .or(sum.filter(|l| !self.base_layers.contains(l)))
+            .unwrap_or(self.default_layer)
+    }
+
+    /// The default layer, set by `DefaultLayer` actions.
+    pub fn default_layer(&self) -> usize {
+        self.default_layer
+    }
+
+    /// The bit set of the features enabled, as `Settings::features`.
+    /// Auto-shift is toggled by `CustomAction::AutoShift` actions.
+    pub fn features(&self) -> u32 {
+        let mut bits = 0;
+        if self.auto_shift_enabled {
+            bits |= features::AUTO_SHIFT;
+        }
+        if self.nkro {
+            bits |= features::NKRO;
+        }
+        bits
+    }
+
+    /// The host OS profile, set by `CustomAction::Os` actions.
+    pub fn os(&self) -> Os {
+        self.os
+    }
+
+    /// The Unicode input method, set by `CustomAction::UnicodeInput`
+    /// actions.
+    pub fn unicode_input(&self) -> InputMethod {
+        self.unicode_input
+    }
+
+    /// The slot and the macro recorded last, until `macro_saved`.
+    pub fn unsaved_macro(&self) -> Option<(usize, &Macro)> {
+        self.unsaved_macro.map(|slot| (slot, &self.macros[slot]))
+    }
+
+    pub fn macro_saved(&mut self) {
+        self.unsaved_macro = None;
+    }
+
+    /// The layers of the layer keys held.
+    fn held_layers(&self) -> impl Iterator<Item = usize> + '_ {
+        self.pressed.iter().filter_map(|p| match p.action {
+            Action::Layer(l) => Some(*l),
+            _ => None,
+        })
+    }
+
+    /// Releases the keys tapped at the previous tick, then processes the
+    /// queued events until a hold-tap key or a dance waits for more, or
+    /// until the next event doesn't fit in the batch of the tick.
+    fn step(&mut self) {
+        while let Some(coord) = self.to_release.pop() {
+            self.release(coord);
+        }
+        let mut batch = Batch::default();
+        loop {
+            let done = if let Some(dance) = self.dance {
+                self.dance(dance, &mut batch)
+            } else if let Some(waiting) = self.waiting {
+                self.follow_waiting(waiting, &mut batch)
+            } else {
+                match self.queue.first() {
+                    Some(&(event, time)) if batch.join(matches!(event, Event::Press(..))) => {
+                        self.queue.remove(0);
+                        self.process(event, time);
+                        true
+                    }
+                    _ => false,
+                }
+            };
+            if !done {
+                break;
+            }
+        }
+    }
+
+    /// Processes `event`, that came at `time`.
+    fn process(&mut self, event: Event, time: u32) {
+        let coord = match event {
+            Event::Press(i, j) => (i, j),
+            Event::Release(i, j) => return self.release((i, j)),
+        };
+        let since_last_press = self.last_press.map(|t| time.wrapping_sub(t));
+        self.last_press = Some(time);
+        match self.action_at(coord) {
+            action @ (Action::HoldTap { .. }
+            | Action::Custom(CustomAction::HoldTap(_) | CustomAction::TapDance(_))) => {
+                self.press_hold_tap(coord, action, time, since_last_press)
+            }
+            Action::Custom(CustomAction::OneShot(one_shot)) => self.press_one_shot(coord, one_shot),
+            Action::Custom(CustomAction::Leader) => self.start_leader(),
+            action @ Action::KeyCode(kc) if self.auto_shifts(*kc) => {
+                self.press_auto_shift(coord, action, time)
+            }
+            action => self.press(coord, action),
+        }
+    }
+
+    fn press(&mut self, coord: (u8, u8), action: &'static Action) {
+        match action {
+            Action::NoOp | Action::Trans => (),
+            Action::DefaultLayer(l) => {
+                if *l < self.layers.len() {
+                    self.default_layer = *l;
+                }
+                self.use_one_shots();
+            }
+            Action::MultipleActions(actions) => {
+                for action in actions.iter() {
+                    self.press(coord, action);
+                }
+            }
+            // Hold-taps and tap-dances nested in another action are tapped once.
+            Action::HoldTap { tap, .. } => self.press(coord, tap),
+            Action::Custom(CustomAction::HoldTap(ht)) => self.press(coord, &ht.tap),
+            Action::Custom(CustomAction::TapDance(dance)) => {
+                self.press(coord, dance.action(1, false))
+            }
+            Action::Custom(CustomAction::OneShot(one_shot)) => self.press(coord, &one_shot.action),
+            Action::Custom(CustomAction::Leader) => (),
+            Action::Custom(CustomAction::AutoShift) => {
+                self.auto_shift_enabled = !self.auto_shift_enabled
+            }
+            Action::Custom(CustomAction::Nkro) => {
+                self.nkro = !self.nkro;
+                self.use_one_shots();
+            }
+            Action::Custom(CustomAction::Bootloader) => {
+                self.reboot = Some((Reboot::Bootloader, self.time))
+            }
+            Action::Custom(CustomAction::Reset) => self.reboot = Some((Reboot::Reset, self.time)),
+            Action::Custom(CustomAction::ToggleLayer(l)) => {
+                if *l < self.layers.len() {
+                    self.latched ^= bit(*l);
+                }
+                self.use_one_shots();
+            }
+            Action::Custom(CustomAction::LayerLock) => {
+                let held = self.held_layers().fold(0, |held, l| held | bit(l));
+                if held & !self.latched == 0 {
+                    self.latched = 0;
+                } else {
+                    self.latched |= held;
+                }
+                self.use_one_shots();
+            }
+            Action::Custom(CustomAction::Os(os)) => {
+                self.os = *os;
+                self.use_one_shots();
+            }
+            Action::Custom(CustomAction::RecordMacro(slot)) => {
+                self.record_macro(*slot);
+                self.use_one_shots();
+            }
+            Action::Custom(CustomAction::StopMacro) => {
+                self.stop_recording();
+                self.use_one_shots();
+            }
+            Action::Custom(CustomAction::PlayMacro(slot)) => {
+                self.play_macro(*slot);
+                self.use_one_shots();
+            }
+            // Ignored if too many are waiting
+            Action::Custom(CustomAction::SequenceMacro(_) | CustomAction::Unicode(_)) => {
+                let _ = self.typed.try_push(action);
+                self.use_one_shots();
+            }
+            Action::Custom(CustomAction::UnicodeInput(method)) => {
+                self.unicode_input = *method;
+                self.use_one_shots();
+            }
+            Action::Custom(CustomAction::CapsWord(caps_word)) => {
+                self.caps_word = match self.caps_word {
+                    Some(_) => None,
+                    None => Some(caps_word),
+                };
+                self.use_one_shots();
+            }
+            _ if self.leading.is_some() && !matches!(action, Action::Layer(_)) => self.lead(action),
+            _ => {
+                let shifted = self.caps_word(action);
+                let _ = self.pressed.try_push(Pressed {
+                    coord,
+                    action,
+                    shifted,
+                });
+                if !is_modifier(action) {
+                    self.use_one_shots();
+                }
+            }
+        }
+    }
+
+    fn release(&mut self, coord: (u8, u8)) {
+        if !self.release_one_shot(coord) {
+            self.pressed.retain(|p| p.coord != coord);
+        }
+    }
+
+    /// Releases `coord` at the next tick, or right away if too many keys
+    /// are to be released, so that no key stays stuck.
+    fn release_next(&mut self, coord: (u8, u8)) {
+        if self.to_release.try_push(coord).is_err() {
+            self.release(coord);
+        }
+    }
+
+    /// The action at `coord` on the current layer.
+    fn action_at(&self, (i, j): (u8, u8)) -> &'static Action {
+        if i == COMBO_ROW {
+            return self.combos.action(j);
+        }
+        let layers = self.layers;
+        let at = |layer: usize| {
+            layers
+                .get(layer)
+                .and_then(|l| l.get(i as usize))
+                .and_then(|r| r.get(j as usize))
+                .unwrap_or(&Action::NoOp)
+        };
+        match at(self.current_layer()) {
+            Action::Trans => at(self.default_layer),
+            action => action,
+        }
+    }
+}
+
+/// The keycode typed by `action`, leaving out its modifiers.
+fn key(action: &Action) -> Option<KeyCode> {
+    let kcs = match action {
+        Action::KeyCode(kc) => core::slice::from_ref(kc),
+        Action::MultipleKeyCodes(kcs) => kcs,
+        _ => &[],
+    };
+    kcs.iter().rev().copied().find(|kc| !kc.is_modifier())
+}
+
+/// Whether `action` only changes how the other keys act.
+fn is_modifier(action: &Action) -> bool {
+    match action {
+        Action::KeyCode(kc) => kc.is_modifier(),
+        Action::MultipleKeyCodes(kcs) => kcs.iter().all(|kc| kc.is_modifier()),
+        Action::Layer(_) => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::action::{Action, CapsWord, CustomAction, HoldTap, Layers, OneShot, TapDance};
+    use crate::keyboard::{Reboot, REBOOT_DELAY_MS};
+    use crate::scenario::Scenario;
+    use keyberon::action::{Action::*, HoldTapConfig};
+    use keyberon::key_code::KeyCode::*;
+    use keyberon_macros::layout;
+
+    const HT: HoldTap = HoldTap::new(KeyCode(LCtrl), KeyCode(A));
+    const FAST: HoldTap = HoldTap {
+        timeout: 50,
+        ..HoldTap::home_row_mod(LShift, S)
+    };
+    const EAGER: HoldTap = HoldTap {
+        hold_on_other_key_press: true,
+        ..HoldTap::new(Layer(1), KeyCode(Space))
+    };
+    const NATIVE: Action = Action::HoldTap {
+        timeout: 100,
+        hold: &KeyCode(LAlt),
+        tap: &KeyCode(Enter),
+        config: HoldTapConfig::Default,
+        tap_hold_interval: 150,
+    };
+
+    const TD: TapDance = TapDance {
+        taps: &[KeyCode(SColon), MultipleKeyCodes(&[LShift, SColon])],
+        holds: &[Layer(1)],
+        timeout: 0,
+    };
+
+    const OS_SHIFT: OneShot = OneShot::new(KeyCode(LShift));
+    const OS_L1: OneShot = OneShot {
+        timeout: 500,
+        ..OneShot::new(Layer(1))
+    };
+
+    const CW: CapsWord = CapsWord::new();
+
+    pub(super) static LAYERS: Layers = layout! {
+        {
+            [{Custom(CustomAction::HoldTap(&HT))} {Custom(CustomAction::HoldTap(&FAST))} B {Custom(CustomAction::TapDance(&TD))} {Custom(CustomAction::OneShot(&OS_SHIFT))} {Custom(CustomAction::CapsWord(&CW))} {Custom(CustomAction::ToggleLayer(1))} {DefaultLayer(1)}]
+            [{Custom(CustomAction::HoldTap(&EAGER))} {NATIVE} C D {Custom(CustomAction::OneShot(&OS_L1))} - {Custom(CustomAction::LayerLock)} n]
+        }
+        { [t t Kb1 t t t t n] [t t Kb2 Kb3 t Dot t {DefaultLayer(0)}] }
+    };
+
+    #[test]
+    fn test_toggle_layer() {
+        Scenario::new(LAYERS)
+            .tap(0, 6)
+            .press(0, 2)
+            .expect(&[Kb1])
+            .release(0, 2)
+            .tap(0, 6)
+            .press(0, 2)
+            .expect(&[B]);
+    }
+
+    #[test]
+    fn test_layer_lock() {
+        Scenario::new(LAYERS)
+            .press(1, 4) // Layer 1
+            .tap(1, 6)
+            .release(1, 4)
+            .press(0, 2)
+            .expect(&[Kb1])
+            .release(0, 2)
+            .tap(1, 6)
+            .press(0, 2)
+            .expect(&[B]);
+        // A one-shot layer tapped
+        Scenario::new(LAYERS)
+            .tap(1, 4)
+            .tap(1, 6)
+            .tick(2000)
+            .press(0, 2)
+            .expect(&[Kb1]);
+    }
+
+    #[test]
+    fn test_base_layers() {
+        // Layer 1 toggled, but a base layer
+        Scenario::new(LAYERS)
+            .base_layers(&[0, 1])
+            .tap(0, 6)
+            .press(0, 2)
+            .expect(&[B]);
+    }
+
+    #[test]
+    fn test_default_layer() {
+        Scenario::new(LAYERS)
+            .tap(0, 7)
+            .press(0, 2)
+            .expect(&[Kb1])
+            .release(0, 2)
+            .tap(1, 7)
+            .press(0, 2)
+            .expect(&[B]);
+    }
+
+    #[test]
+    fn test_reboot() {
+        static LAYERS: Layers = layout! {
+            { [{Custom(CustomAction::Bootloader)} {Custom(CustomAction::Reset)} A] }
+        };
+        let boot = Some(Reboot::Bootloader);
+        Scenario::new(LAYERS)
+            .expect_reboot(None, None)
+            .press(0, 2)
+            .press(0, 0)
+            .expect_reboot(boot, None)
+            .release(0, 0)
+            .tick(REBOOT_DELAY_MS)
+            .expect_reboot(boot, None)
+            // Once the last key is released
+            .release(0, 2)
+            .tick(REBOOT_DELAY_MS - 2)
+            .expect_reboot(boot, None)
+            .tick(1)
+            .expect_reboot(boot, boot);
+        let reset = Some(Reboot::Reset);
+        Scenario::new(LAYERS)
+            .tap(0, 1)
+            .tick(REBOOT_DELAY_MS)
+            .expect_reboot(reset, reset);
+    }
+}
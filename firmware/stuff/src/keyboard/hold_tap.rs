@@ -0,0 +1,481 @@
+//! Hold-tap and tap-dance keys, and the keys waiting for auto-shift:
+//! they hold back the following events until they are decided.
+
+use super::{Batch, Keyboard};
+use crate::action::{Action, CustomAction, TapDance};
+use arrayvec::ArrayVec;
+use keyberon::action::HoldTapConfig;
+use keyberon::key_code::KeyCode;
+use keyberon::layout::Event;
+
+/// A hold-tap key waiting to be decided.
+#[derive(Clone, Copy)]
+pub(super) struct Waiting {
+    coord: (u8, u8),
+    since: u32,
+    hold: &'static Action,
+    tap: &'static Action,
+    timeout: u16,
+    config: HoldTapConfig,
+    /// Waiting for auto-shift, holding being shifting the key.
+    auto_shift: bool,
+}
+
+/// A tap-dance key being tapped.
+#[derive(Clone, Copy)]
+pub(super) struct Dance {
+    coord: (u8, u8),
+    dance: &'static TapDance,
+    count: usize,
+    held: bool,
+    /// Time of the last press or release of the key.
+    since: u32,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Decision {
+    Hold,
+    Tap,
+}
+
+impl Keyboard {
+    /// Presses the hold-tap or tap-dance key `action` at `coord`, pressed
+    /// at `time`, that waits to be decided unless pressed again soon after
+    /// a tap, or `since_last_press` is shorter than its prior idle time.
+    pub(super) fn press_hold_tap(
+        &mut self,
+        coord: (u8, u8),
+        action: &'static Action,
+        time: u32,
+        since_last_press: Option<u32>,
+    ) {
+        match action {
+            Action::HoldTap {
+                timeout,
+                hold,
+                tap,
+                config,
+                tap_hold_interval,
+            } => {
+                let quick_tap = matches!(
+                    self.last_tap,
+                    Some((c, t)) if c == coord && self.time.wrapping_sub(t) < *tap_hold_interval as u32
+                );
+                if quick_tap {
+                    self.press(coord, tap);
+                } else {
+                    self.wait(coord, time, hold, tap, *timeout, *config);
+                }
+            }
+            Action::Custom(CustomAction::HoldTap(ht)) => {
+                if since_last_press.is_some_and(|t| t < ht.prior_idle as u32) {
+                    self.press(coord, &ht.tap);
+                    return;
+                }
+                let config = if ht.hold_on_other_key_press {
+                    HoldTapConfig::HoldOnOtherKeyPress
+                } else if ht.permissive_hold {
+                    HoldTapConfig::PermissiveHold
+                } else {
+                    HoldTapConfig::Default
+                };
+                let timeout = match ht.timeout {
+                    0 => self.tapping_term,
+                    timeout => timeout,
+                };
+                self.wait(coord, time, &ht.hold, &ht.tap, timeout, config);
+            }
+            Action::Custom(CustomAction::TapDance(dance)) => {
+                self.dance = Some(Dance {
+                    coord,
+                    dance,
+                    count: 1,
+                    held: true,
+                    since: time,
+                });
+            }
+            action => self.press(coord, action),
+        }
+    }
+
+    /// Presses the key `action` at `coord`, pressed at `time`, that waits
+    /// for auto-shift.
+    pub(super) fn press_auto_shift(&mut self, coord: (u8, u8), action: &'static Action, time: u32) {
+        let timeout = self.auto_shift.map_or(0, |a| a.timeout);
+        self.waiting = Some(Waiting {
+            coord,
+            since: time,
+            hold: action,
+            tap: action,
+            timeout,
+            config: HoldTapConfig::Default,
+            auto_shift: true,
+        });
+    }
+
+    fn wait(
+        &mut self,
+        coord: (u8, u8),
+        since: u32,
+        hold: &'static Action,
+        tap: &'static Action,
+        timeout: u16,
+        config: HoldTapConfig,
+    ) {
+        self.waiting = Some(Waiting {
+            coord,
+            since,
+            hold,
+            tap,
+            timeout,
+            config,
+            auto_shift: false,
+        });
+    }
+
+    /// Presses the action of the waiting key once decided, if it goes in
+    /// `batch`. Returns whether it was.
+    pub(super) fn follow_waiting(&mut self, waiting: Waiting, batch: &mut Batch) -> bool {
+        match self.decide(&waiting) {
+            Some(decision) if batch.join(true) => {
+                self.decided(waiting, decision);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Holds the waiting key, if any, undecided as it is.
+    pub(super) fn hold_waiting(&mut self) {
+        if let Some(waiting) = self.waiting {
+            self.decided(waiting, Decision::Hold);
+        }
+    }
+
+    fn decided(&mut self, waiting: Waiting, decision: Decision) {
+        self.waiting = None;
+        if decision == Decision::Tap {
+            self.last_tap = Some((waiting.coord, self.time));
+            self.press(waiting.coord, waiting.tap);
+        } else {
+            self.press(waiting.coord, waiting.hold);
+            if waiting.auto_shift {
+                self.shift(waiting.coord);
+            }
+        }
+    }
+
+    fn decide(&self, waiting: &Waiting) -> Option<Decision> {
+        let mut others = ArrayVec::<(u8, u8), { super::QUEUE_LEN }>::new();
+        for &(event, time) in &self.queue {
+            // Held past its timeout before the event came
+            if time.wrapping_sub(waiting.since) >= waiting.timeout as u32 {
+                return Some(Decision::Hold);
+            }
+            match event {
+                Event::Release(i, j) if (i, j) == waiting.coord => return Some(Decision::Tap),
+                Event::Press(..) if waiting.auto_shift => return Some(Decision::Tap),
+                Event::Press(..) if waiting.config == HoldTapConfig::HoldOnOtherKeyPress => {
+                    return Some(Decision::Hold)
+                }
+                Event::Press(i, j) => others.push((i, j)),
+                Event::Release(i, j)
+                    if waiting.config == HoldTapConfig::PermissiveHold
+                        && others.contains(&(i, j)) =>
+                {
+                    return Some(Decision::Hold)
+                }
+                Event::Release(..) => (),
+            }
+        }
+        if self.time.wrapping_sub(waiting.since) >= waiting.timeout as u32 {
+            return Some(Decision::Hold);
+        }
+        None
+    }
+
+    /// Follows the dance with the next event, ending it on another key
+    /// pressed or on its timeout, as the events of `batch` allow.
+    /// Returns whether it went on.
+    pub(super) fn dance(&mut self, mut dance: Dance, batch: &mut Batch) -> bool {
+        let timeout = match dance.dance.timeout {
+            0 => self.tapping_term,
+            timeout => timeout,
+        };
+        // Ended on its timeout, before the next event came if any
+        let next = self.queue.first().map_or(self.time, |&(_, time)| time);
+        if next.wrapping_sub(dance.since) >= timeout as u32 {
+            if !batch.join(true) {
+                return false;
+            }
+            self.end_dance(dance);
+            return true;
+        }
+        match self.queue.first().copied() {
+            Some((Event::Press(i, j), time)) if (i, j) == dance.coord => {
+                self.queue.remove(0);
+                dance.count += 1;
+                dance.held = true;
+                dance.since = time;
+            }
+            Some((Event::Release(i, j), time)) if (i, j) == dance.coord => {
+                self.queue.remove(0);
+                dance.held = false;
+                dance.since = time;
+            }
+            Some((event @ Event::Release(..), time)) if batch.join(false) => {
+                self.queue.remove(0);
+                self.process(event, time);
+            }
+            // Another key pressed ends the dance
+            Some((Event::Press(..), _)) if batch.join(true) => {
+                self.end_dance(dance);
+                return true;
+            }
+            _ => return false,
+        }
+        self.dance = Some(dance);
+        true
+    }
+
+    fn end_dance(&mut self, dance: Dance) {
+        self.dance = None;
+        self.press(dance.coord, dance.dance.action(dance.count, dance.held));
+        if !dance.held {
+            self.release_next(dance.coord);
+        }
+    }
+
+    /// Shifts the keys pressed at `coord`.
+    fn shift(&mut self, coord: (u8, u8)) {
+        for p in self.pressed.iter_mut().filter(|p| p.coord == coord) {
+            p.shifted = true;
+        }
+    }
+
+    pub(super) fn auto_shifts(&self, kc: KeyCode) -> bool {
+        match self.auto_shift {
+            Some(auto_shift) => self.auto_shift_enabled && auto_shift.shifts(kc),
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::keyboard::tests::LAYERS;
+    use crate::scenario::Scenario;
+    use keyberon::key_code::KeyCode::*;
+
+    #[test]
+    fn test_tap() {
+        Scenario::new(LAYERS)
+            .press(0, 0)
+            .tick(100)
+            .expect(&[])
+            .release(0, 0)
+            .expect(&[A])
+            .tick(1)
+            .expect(&[]);
+    }
+
+    #[test]
+    fn test_hold_on_timeout() {
+        // The tapping term of the settings
+        Scenario::new(LAYERS)
+            .press(0, 0)
+            .tick(199)
+            .expect(&[])
+            .tick(1)
+            .expect(&[LCtrl])
+            .release(0, 0)
+            .tick(1)
+            .expect(&[]);
+        // and a timeout of its own
+        Scenario::new(LAYERS)
+            .press(0, 1)
+            .tick(49)
+            .expect(&[])
+            .tick(1)
+            .expect(&[LShift]);
+    }
+
+    #[test]
+    fn test_other_keys_wait() {
+        // Rolled: a, then b
+        Scenario::new(LAYERS)
+            .press(0, 0)
+            .press(0, 2)
+            .release(0, 0)
+            .expect(&[A, B])
+            .tick(1)
+            .expect(&[B])
+            .release(0, 2)
+            .expect(&[]);
+        // Without permissive hold, a nested tap is still a tap
+        Scenario::new(LAYERS)
+            .press(0, 0)
+            .tap(0, 2)
+            .release(0, 0)
+            .expect(&[A, B])
+            .tick(10)
+            .expect(&[]);
+        // however many
+        let mut scenario = Scenario::new(LAYERS).press(0, 0);
+        for _ in 0..20 {
+            scenario = scenario.tap(0, 2);
+        }
+        scenario.release(0, 0).expect(&[A, B]);
+    }
+
+    #[test]
+    fn test_permissive_hold() {
+        Scenario::new(LAYERS)
+            .tick(200)
+            .press(0, 1)
+            .tap(0, 2)
+            .expect(&[LShift, B])
+            .tick(1)
+            .expect(&[LShift])
+            .release(0, 1)
+            .expect(&[]);
+    }
+
+    #[test]
+    fn test_hold_on_other_key_press() {
+        Scenario::new(LAYERS)
+            .press(1, 0)
+            .press(0, 2)
+            .tick(1)
+            .expect(&[Kb1])
+            .release(0, 2)
+            .release(1, 0)
+            .press(1, 0)
+            .release(1, 0)
+            .expect(&[Space]);
+    }
+
+    #[test]
+    fn test_prior_idle() {
+        // Typing fast, s is tapped right away
+        Scenario::new(LAYERS)
+            .tap(0, 2)
+            .tick(100)
+            .press(0, 1)
+            .expect(&[S])
+            .tick(100)
+            .expect(&[S])
+            .release(0, 1)
+            .expect(&[]);
+        // but held after a pause
+        Scenario::new(LAYERS)
+            .tap(0, 2)
+            .tick(150)
+            .press(0, 1)
+            .tick(60)
+            .expect(&[LShift]);
+    }
+
+    #[test]
+    fn test_queued() {
+        // Pressed while another one waits, its timeout runs from its press
+        Scenario::new(LAYERS)
+            .press(0, 0)
+            .tick(159)
+            .press(0, 1)
+            .tick(39)
+            .expect(&[])
+            .tick(1)
+            .expect(&[LCtrl])
+            .tick(9)
+            .expect(&[LCtrl])
+            .tick(1)
+            .expect(&[LCtrl, LShift]);
+        // and so does its prior idle time
+        Scenario::new(LAYERS)
+            .press(0, 0)
+            .tick(99)
+            .press(0, 1)
+            .tick(100)
+            .expect(&[LCtrl, S]);
+    }
+
+    #[test]
+    fn test_native_hold_tap() {
+        Scenario::new(LAYERS)
+            .press(1, 1)
+            .tick(100)
+            .expect(&[LAlt])
+            .release(1, 1)
+            .tap(1, 1)
+            .expect(&[Enter])
+            .tick(10)
+            // Pressed again soon after a tap, it taps without waiting
+            .press(1, 1)
+            .expect(&[Enter])
+            .tick(200)
+            .expect(&[Enter]);
+    }
+
+    #[test]
+    fn test_tap_dance() {
+        // Once
+        Scenario::new(LAYERS)
+            .tap(0, 3)
+            .tick(199)
+            .expect(&[])
+            .tick(1)
+            .expect(&[SColon])
+            .tick(1)
+            .expect(&[]);
+        // twice
+        Scenario::new(LAYERS)
+            .tap(0, 3)
+            .tick(100)
+            .tap(0, 3)
+            .tick(199)
+            .expect(&[])
+            .tick(1)
+            .expect(&[LShift, SColon])
+            .tick(1)
+            .expect(&[]);
+        // and more
+        Scenario::new(LAYERS)
+            .tap(0, 3)
+            .tap(0, 3)
+            .tap(0, 3)
+            .tick(200)
+            .expect(&[LShift, SColon]);
+    }
+
+    #[test]
+    fn test_tap_dance_hold() {
+        Scenario::new(LAYERS)
+            .press(0, 3)
+            .tick(200)
+            .press(1, 3)
+            .expect(&[Kb3])
+            .release(1, 3)
+            .release(0, 3)
+            .press(1, 3)
+            .expect(&[D]);
+        // After a tap, with no hold action for two taps
+        Scenario::new(LAYERS)
+            .tap(0, 3)
+            .press(0, 3)
+            .tick(300)
+            .expect(&[LShift, SColon])
+            .release(0, 3)
+            .expect(&[]);
+    }
+
+    #[test]
+    fn test_tap_dance_interrupted() {
+        Scenario::new(LAYERS)
+            .tap(0, 3)
+            .press(1, 3)
+            .expect(&[SColon, D])
+            .tick(1)
+            .expect(&[D]);
+    }
+}
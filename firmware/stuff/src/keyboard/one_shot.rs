@@ -0,0 +1,267 @@
+//! One-shot keys, caps word and the leader key, that act on the keys
+//! pressed after them.
+
+use super::{key, Keyboard};
+use crate::action::{Action, OneShot};
+
+/// Maximum number of one-shot keys pressed at once.
+pub(super) const MAX_ONE_SHOTS: usize = 4;
+
+/// Row of the virtual keys of the one-shot keys tapped.
+const ONE_SHOT_ROW: u8 = u8::MAX - 1;
+
+/// Virtual key of the leader sequence done.
+const LEADER_COORD: (u8, u8) = (u8::MAX - 2, 0);
+
+/// A one-shot key held, or tapped and waiting for another key.
+#[derive(Clone, Copy)]
+pub(super) struct OneShotKey {
+    coord: (u8, u8),
+    one_shot: &'static OneShot,
+    /// Whether another key was pressed while it was held.
+    used: bool,
+    /// Time it was tapped.
+    tapped: Option<u32>,
+}
+
+/// A leader sequence being typed.
+#[derive(Clone, Copy)]
+pub(super) struct Leading {
+    /// Node of the trie of the keys typed.
+    node: usize,
+    /// Time of the last key.
+    since: u32,
+}
+
+impl Keyboard {
+    /// Presses the one-shot key at `coord`, or cancels it if it was
+    /// tapped and is waiting.
+    pub(super) fn press_one_shot(&mut self, coord: (u8, u8), one_shot: &'static OneShot) {
+        let tapped = self
+            .one_shots
+            .iter()
+            .position(|o| o.tapped.is_some() && o.one_shot == one_shot);
+        if let Some(i) = tapped {
+            let cancelled = self.one_shots.remove(i);
+            self.pressed.retain(|p| p.coord != cancelled.coord);
+            return;
+        }
+        self.press(coord, &one_shot.action);
+        let key = OneShotKey {
+            coord,
+            one_shot,
+            used: false,
+            tapped: None,
+        };
+        // Held as usual if too many one-shot keys are pressed
+        let _ = self.one_shots.try_push(key);
+    }
+
+    /// Releases the one-shot key at `coord`, if any. Returns true if it
+    /// was tapped, and so stays pressed.
+    pub(super) fn release_one_shot(&mut self, coord: (u8, u8)) -> bool {
+        let one_shot = self
+            .one_shots
+            .iter_mut()
+            .find(|o| o.coord == coord && o.tapped.is_none());
+        if let Some(one_shot) = one_shot {
+            if !one_shot.used {
+                // Tapped: moved to a virtual key, to release later
+                let virtual_coord = (ONE_SHOT_ROW, self.next_one_shot);
+                self.next_one_shot = self.next_one_shot.wrapping_add(1);
+                one_shot.coord = virtual_coord;
+                one_shot.tapped = Some(self.time);
+                for p in self.pressed.iter_mut().filter(|p| p.coord == coord) {
+                    p.coord = virtual_coord;
+                }
+                return true;
+            }
+            self.one_shots.retain(|o| o.coord != coord);
+        }
+        false
+    }
+
+    /// Applies the one-shot keys to the key just pressed: the held ones
+    /// now act as usual, and the tapped ones are released next.
+    pub(super) fn use_one_shots(&mut self) {
+        for one_shot in &mut self.one_shots {
+            one_shot.used = true;
+            if one_shot.tapped.is_some() && self.to_release.try_push(one_shot.coord).is_err() {
+                self.pressed.retain(|p| p.coord != one_shot.coord);
+            }
+        }
+        self.one_shots.retain(|o| o.tapped.is_none());
+    }
+
+    pub(super) fn expire_one_shots(&mut self) {
+        let time = self.time;
+        let expired = |o: &OneShotKey| {
+            o.tapped
+                .is_some_and(|t| time.wrapping_sub(t) >= o.one_shot.timeout as u32)
+        };
+        for one_shot in self.one_shots.iter().filter(|o| expired(o)) {
+            self.pressed.retain(|p| p.coord != one_shot.coord);
+        }
+        self.one_shots.retain(|o| !expired(o));
+    }
+
+    /// Whether caps word shifts `action`, ending the word if `action` is
+    /// not part of it.
+    pub(super) fn caps_word(&mut self, action: &Action) -> bool {
+        let (caps_word, key) = match (self.caps_word, key(action)) {
+            (Some(caps_word), Some(key)) => (caps_word, key),
+            _ => return false,
+        };
+        if !caps_word.continues(key) {
+            self.caps_word = None;
+        }
+        caps_word.shifts(key)
+    }
+
+    pub(super) fn start_leader(&mut self) {
+        self.leading = Some(Leading {
+            node: 0,
+            since: self.time,
+        });
+    }
+
+    /// Follows the leader sequence with the key of `action`, that is not
+    /// pressed. Modifiers are ignored.
+    pub(super) fn lead(&mut self, action: &Action) {
+        let key = match key(action) {
+            Some(key) => key,
+            None => return,
+        };
+        let leading = match self.leading.take() {
+            Some(leading) => leading,
+            None => return,
+        };
+        match self.leader.child(leading.node, key) {
+            Some(node) if self.leader.has_children(node) => {
+                self.leading = Some(Leading {
+                    node,
+                    since: self.time,
+                })
+            }
+            Some(node) => self.end_leader(node),
+            // Unknown sequence: aborted
+            None => (),
+        }
+    }
+
+    pub(super) fn expire_leader(&mut self) {
+        if let Some(leading) = self.leading {
+            if self.time.wrapping_sub(leading.since) >= self.leader_timeout as u32 {
+                self.leading = None;
+                self.end_leader(leading.node);
+            }
+        }
+    }
+
+    /// Taps the action of the sequence ending at `node`, if any.
+    fn end_leader(&mut self, node: usize) {
+        if let Some(action) = self.leader.action(node) {
+            self.press(LEADER_COORD, action);
+            self.release_next(LEADER_COORD);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::keyboard::tests::LAYERS;
+    use crate::scenario::Scenario;
+    use keyberon::key_code::KeyCode::*;
+
+    #[test]
+    fn test_one_shot() {
+        // Tapped, for the next key
+        Scenario::new(LAYERS)
+            .tap(0, 4)
+            .expect(&[LShift])
+            .tick(100)
+            .press(0, 2)
+            .expect(&[LShift, B])
+            .tick(1)
+            .expect(&[B])
+            .release(0, 2)
+            .expect(&[]);
+        // tapped again, cancelled
+        Scenario::new(LAYERS)
+            .tap(0, 4)
+            .tap(0, 4)
+            .expect(&[])
+            .press(0, 2)
+            .expect(&[B]);
+        // held, as usual
+        Scenario::new(LAYERS)
+            .press(0, 4)
+            .press(0, 2)
+            .release(0, 2)
+            .expect(&[LShift])
+            .release(0, 4)
+            .expect(&[]);
+    }
+
+    #[test]
+    fn test_one_shot_timeout() {
+        Scenario::new(LAYERS)
+            .tap(0, 4)
+            .tick(999)
+            .expect(&[LShift])
+            .tick(1)
+            .expect(&[])
+            .press(0, 2)
+            .expect(&[B]);
+    }
+
+    #[test]
+    fn test_one_shot_layer() {
+        // Stacked with a one-shot modifier
+        Scenario::new(LAYERS)
+            .tap(1, 4)
+            .tap(0, 4)
+            .press(1, 3)
+            .expect(&[LShift, Kb3])
+            .tick(1)
+            .expect(&[Kb3])
+            .release(1, 3)
+            .press(1, 3)
+            .expect(&[D]);
+    }
+
+    #[test]
+    fn test_caps_word() {
+        Scenario::new(LAYERS)
+            .tap(0, 5)
+            .tap(0, 2)
+            .press(1, 5)
+            .expect(&[Minus, LShift])
+            .release(1, 5)
+            .press(1, 4) // Layer 1
+            .tap(1, 2) // 2, unshifted
+            .press(1, 5) // ., that ends the word
+            .expect(&[Dot])
+            .release(1, 5)
+            .release(1, 4)
+            .press(0, 2)
+            .expect(&[B]);
+        // Pressed again, it stops
+        Scenario::new(LAYERS)
+            .tap(0, 5)
+            .press(1, 3)
+            .expect(&[D, LShift])
+            .release(1, 3)
+            .tap(0, 5)
+            .press(1, 3)
+            .expect(&[D]);
+        // A key rolled into isn't shifted
+        Scenario::new(LAYERS)
+            .tap(0, 5)
+            .press(0, 2)
+            .expect(&[B, LShift])
+            .press(1, 4) // Layer 1
+            .press(1, 2)
+            .expect(&[B, Kb2]);
+    }
+}
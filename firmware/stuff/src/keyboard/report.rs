@@ -0,0 +1,158 @@
+//! The reports of the keys pressed: the keycodes in the boot or the
+//! NKRO report, and the mouse and media reports.
+
+use super::{is_modifier, Keyboard};
+use crate::action::{Action, CustomAction};
+use crate::key_override;
+use crate::media::{self, MediaReport};
+use crate::mouse::{self, MouseReport};
+use crate::nkro::NkroReport;
+use keyberon::key_code::{KbHidReport, KeyCode};
+
+impl Keyboard {
+    /// Moves the mouse and updates the media controls of the keys held.
+    pub(super) fn update_mouse_media(&mut self) {
+        let mut mouse_held = mouse::Held::default();
+        let mut media_held = media::Held::default();
+        for p in &self.pressed {
+            match p.action {
+                Action::Custom(CustomAction::Mouse(action)) => mouse_held.add(*action),
+                Action::Custom(CustomAction::Media(key)) => media_held.add(*key),
+                _ => (),
+            }
+        }
+        self.mouse.tick(self.time, mouse_held);
+        self.media.tick(media_held);
+    }
+
+    /// Sets the keycodes of the keys held, up to `MAX_KEYCODES`, with the
+    /// shift of caps word and auto-shift and the key overrides applied.
+    pub(super) fn update_keycodes(&mut self) {
+        self.keycodes.clear();
+        let keycodes = self.pressed.iter().flat_map(|p| match p.action {
+            Action::KeyCode(kc) => core::slice::from_ref(kc),
+            Action::MultipleKeyCodes(kcs) => kcs,
+            _ => &[],
+        });
+        for &kc in keycodes {
+            if self.keycodes.try_push(kc).is_err() {
+                break;
+            }
+        }
+        // Shifted by caps word or auto-shift while the last key pressed
+        // is, so that the keys rolled into aren't
+        let shift = KeyCode::LShift;
+        let last = self.pressed.iter().rev().find(|p| !is_modifier(p.action));
+        if last.is_some_and(|p| p.shifted) && !self.keycodes.contains(&shift) {
+            let _ = self.keycodes.try_push(shift);
+        }
+        let layer = self.current_layer();
+        key_override::apply(self.key_overrides, layer, &mut self.keycodes);
+    }
+
+    /// The keycodes pressed, as of the last tick.
+    pub fn keycodes(&self) -> &[KeyCode] {
+        &self.keycodes
+    }
+
+    /// The boot report of the keycodes, as translated for the OS
+    /// profile. Empty in NKRO mode.
+    pub fn report(&self) -> KbHidReport {
+        if self.nkro() {
+            KbHidReport::default()
+        } else {
+            self.translated().collect()
+        }
+    }
+
+    /// The NKRO report of the keycodes, as `report`. Empty in 6KRO mode.
+    pub fn nkro_report(&self) -> NkroReport {
+        if self.nkro() {
+            self.translated().collect()
+        } else {
+            NkroReport::default()
+        }
+    }
+
+    /// Whether the keys go in the NKRO report, toggled by
+    /// `CustomAction::Nkro` actions, and never while the host selected
+    /// the boot protocol.
+    pub fn nkro(&self) -> bool {
+        self.nkro && !self.boot_protocol
+    }
+
+    /// Tells whether the host selected the boot protocol, as a BIOS does,
+    /// rather than the report one, as it is after a USB reset.
+    pub fn set_boot_protocol(&mut self, boot: bool) {
+        self.boot_protocol = boot;
+    }
+
+    fn translated(&self) -> impl Iterator<Item = KeyCode> + '_ {
+        self.keycodes
+            .iter()
+            .flat_map(move |kc| self.os.translate(kc))
+            .copied()
+    }
+
+    /// The mouse report to send, if the mouse moved or its buttons
+    /// changed.
+    pub fn mouse_report(&self) -> Option<MouseReport> {
+        self.mouse.report()
+    }
+
+    /// Tells that `report`, from `mouse_report`, was sent to the host.
+    pub fn mouse_report_sent(&mut self, report: &MouseReport) {
+        self.mouse.report_sent(report);
+    }
+
+    /// The media report to send, if a control changed.
+    pub fn media_report(&self) -> Option<MediaReport> {
+        self.media.report()
+    }
+
+    /// Tells that `report`, from `media_report`, was sent to the host.
+    pub fn media_report_sent(&mut self, report: &MediaReport) {
+        self.media.report_sent(report);
+    }
+
+    /// Tells that the last report was sent to the host, or that there was
+    /// nothing to send. Sequence macros wait for it between reports.
+    pub fn report_sent(&mut self) {
+        self.sent = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::action::{CustomAction, Layers};
+    use crate::keyboard::MAX_KEYCODES;
+    use crate::scenario::Scenario;
+    use crate::sequence_macro::{SequenceMacro, Step};
+    use keyberon::action::Action::*;
+    use keyberon::key_code::KeyCode::{self, *};
+    use keyberon_macros::layout;
+
+    static HI: SequenceMacro = SequenceMacro {
+        steps: &[Step::Tap(&[LShift, H])],
+    };
+
+    /// More keycodes than the keyboard holds.
+    static MANY: &[KeyCode] = &[
+        A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z, Kb1, Kb2,
+        Kb3, Kb4, Kb5, Kb6, Kb7,
+    ];
+
+    static LAYERS: Layers = layout! {
+        { [{Custom(CustomAction::SequenceMacro(&HI))} {MultipleKeyCodes(MANY)}] }
+    };
+
+    #[test]
+    fn test_many_keys_held() {
+        // The keycodes past the maximum are left out, and the rest of the
+        // tick goes on
+        let scenario = Scenario::new(LAYERS).press(0, 1);
+        assert_eq!(scenario.keyboard().keycodes(), &MANY[..MAX_KEYCODES]);
+        let scenario = scenario.tap(0, 0).tick(10);
+        assert_eq!(scenario.sent(&[LShift, H]), 1);
+    }
+}
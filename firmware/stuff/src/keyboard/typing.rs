@@ -0,0 +1,151 @@
+//! The keycodes typed by the keyboard itself: the dynamic macros
+//! recorded and played, the sequence macros and the Unicode characters.
+
+use super::{Keyboard, MAX_KEYCODES};
+use crate::action::{Action, CustomAction};
+use crate::dynamic_macro::{Macro, SLOTS};
+use crate::sequence_macro::Typing;
+use arrayvec::ArrayVec;
+use keyberon::key_code::KeyCode;
+
+/// Maximum number of sequence macros and Unicode characters waiting to
+/// be typed.
+pub(super) const MAX_TYPED: usize = 4;
+
+pub(super) struct Recording {
+    slot: usize,
+    recorded: Macro,
+    /// Time of the last step, none before the first.
+    last: Option<u32>,
+    /// Keycodes of the previous tick.
+    keycodes: ArrayVec<KeyCode, MAX_KEYCODES>,
+}
+
+pub(super) struct Playing {
+    slot: usize,
+    /// Index of the next step.
+    next: usize,
+    /// Time of the last step.
+    since: u32,
+    keycodes: ArrayVec<KeyCode, MAX_KEYCODES>,
+}
+
+impl Keyboard {
+    /// Starts recording the macro of `slot`, or stops recording.
+    pub(super) fn record_macro(&mut self, slot: usize) {
+        if self.recording.is_some() {
+            self.stop_recording();
+        } else if slot < SLOTS {
+            self.recording = Some(Recording {
+                slot,
+                recorded: Macro::default(),
+                last: None,
+                keycodes: self.keycodes.clone(),
+            });
+        }
+    }
+
+    /// Records the changes of the keycodes, the releases first. Stops
+    /// recording once the macro is full.
+    pub(super) fn record(&mut self) {
+        let Recording {
+            recorded,
+            last,
+            keycodes,
+            ..
+        } = match &mut self.recording {
+            Some(recording) => recording,
+            None => return,
+        };
+        let (time, current) = (self.time, &self.keycodes);
+        let released = keycodes.iter().filter(|kc| !current.contains(kc));
+        let pressed = current.iter().filter(|kc| !keycodes.contains(kc));
+        let changes = released.map(|&kc| (kc, false));
+        for (kc, down) in changes.chain(pressed.map(|&kc| (kc, true))) {
+            recorded.push(last.map_or(0, |l| time.wrapping_sub(l)), kc, down);
+            *last = Some(time);
+        }
+        *keycodes = current.clone();
+        if recorded.is_full() {
+            self.stop_recording();
+        }
+    }
+
+    pub(super) fn stop_recording(&mut self) {
+        if let Some(recording) = self.recording.take() {
+            self.macros[recording.slot] = recording.recorded;
+            self.unsaved_macro = Some(recording.slot);
+        }
+    }
+
+    /// Starts playing the macro of `slot`, but not while recording, as
+    /// the macro played is not recorded.
+    pub(super) fn play_macro(&mut self, slot: usize) {
+        if self.recording.is_none() && slot < SLOTS {
+            self.playing = Some(Playing {
+                slot,
+                next: 0,
+                since: self.time,
+                keycodes: ArrayVec::new(),
+            });
+        }
+    }
+
+    /// Plays the steps of the macro that are due, adding its keycodes.
+    pub(super) fn play(&mut self) {
+        let playing = match &mut self.playing {
+            Some(playing) => playing,
+            None => return,
+        };
+        let steps = self.macros[playing.slot].steps();
+        while let Some(step) = steps.get(playing.next) {
+            if self.time.wrapping_sub(playing.since) < step.delay as u32 {
+                break;
+            }
+            if step.pressed {
+                let _ = playing.keycodes.try_push(step.keycode);
+            } else {
+                playing.keycodes.retain(|kc| *kc != step.keycode);
+            }
+            playing.since = self.time;
+            playing.next += 1;
+        }
+        for &kc in &playing.keycodes {
+            let _ = self.keycodes.try_push(kc);
+        }
+        // The keycodes still pressed are released at the next tick
+        if playing.next == steps.len() {
+            self.playing = None;
+        }
+    }
+
+    /// Types the sequence macros and Unicode characters one after the
+    /// other, changing the report only once the previous one is sent.
+    /// The keys held are held back meanwhile.
+    pub(super) fn type_sequence(&mut self) {
+        if self.typing.is_none() && !self.typed.is_empty() {
+            self.typing = match self.typed.remove(0) {
+                Action::Custom(CustomAction::SequenceMacro(sequence)) => {
+                    Some(Typing::new(sequence, self.unicode_input))
+                }
+                Action::Custom(CustomAction::Unicode(c)) => {
+                    Some(Typing::unicode(*c, self.unicode_input))
+                }
+                _ => None,
+            };
+        }
+        let typing = match &mut self.typing {
+            Some(typing) => typing,
+            None => return,
+        };
+        if self.sent && typing.advance(self.time) {
+            self.sent = false;
+        }
+        self.keycodes.clear();
+        self.keycodes.extend(typing.keycodes().iter().copied());
+        // Once its last report is sent
+        if typing.is_done() && self.sent {
+            self.typing = None;
+        }
+    }
+}
@@ -1,152 +1,676 @@
 use keyberon_macros::layout;
-use keyberon::action::{k, l, m, Action, Action::*, HoldTapConfig};
+use keyberon::action::Action::*;
 use keyberon::key_code::KeyCode::*;
+use crate::action::{Action, CapsWord, CustomAction, Layers};
+use crate::auto_shift::AutoShift;
+use crate::combo::Combo;
+use crate::conditional::ConditionalLayer;
+use crate::key_override::{mods, KeyOverride};
+use crate::leader::{Leader, Sequence};
+use crate::leds::LedState;
+use crate::media::MediaKey;
+use crate::mouse::{buttons, MouseAction, MouseKeys};
+use crate::os::Os;
+use crate::sequence_macro::{SequenceMacro, Step};
+use crate::unicode::InputMethod;
 
-pub static LAYERS: keyberon::layout::Layers = layout! {
+const CAPS_WORD: CapsWord = CapsWord::new();
+
+const fn ms(action: MouseAction) -> Action {
+    Custom(CustomAction::Mouse(action))
+}
+
+const fn media(key: MediaKey) -> Action {
+    Custom(CustomAction::Media(key))
+}
+
+// Base layers, the default layer being one of them. The other layers
+// are shared, their transparent keys falling back to the default layer.
+pub const QWERTY: usize = 0;
+pub const COLEMAK: usize = 4;
+pub const DVORAK: usize = 5;
+pub static BASE_LAYERS: &[usize] = &[QWERTY, COLEMAK, DVORAK];
+
+pub static LAYERS: Layers = layout! {
     {
         [ Tab    Q W E R T n                    n Y U I O P 0 ]
-        [ n      A S D F G Tab                  n H J K L ; Quote   ]
-        [ n      Z X C V B Escape           Enter N M , . / Escape  ]
+        [ n      A S D F G Tab                  n H J K L ; Quote ]
+        [ n      Z X C V B Escape           Enter N M , . / Escape ]
         [ n n n LGui LShift BSpace LCtrl     RAlt Space (1) - n n n ]
     }
     {
         [ t      ! @ '{' '}' |   n    n PgUp   7 8 9 *    t ]
-        [ t      # $ '(' ')' '`' n    n PgDown 4 5 6 +    = ]
-        [ t      % ^ '[' ']' ~   n    n &      1 2 3 '\\' = ]
+        [ t      # $ '(' ')' '`' {Custom(CustomAction::CapsWord(&CAPS_WORD))}    n PgDown 4 5 6 +    = ]
+        [ t      % ^ '[' ']' ~   {Custom(CustomAction::LayerLock)}    {Custom(CustomAction::Leader)} &      1 2 3 '\\' = ]
         [ n      n n t   (2)  (1) t    t (1)    t n n n    n ]
     }
     {
-        [ n n n n n n n            n n PgUp n n n n ]
-        [ n n n PgDown n n n       n Left Down Up Right n n ]
-        [ n n n n n n n            n n n n n n n ]
+        [ n {Custom(CustomAction::PlayMacro(0))} {Custom(CustomAction::PlayMacro(1))} {Custom(CustomAction::PlayMacro(2))} {ms(MouseAction::WheelUp)} {ms(MouseAction::Up)} n            n n PgUp {Custom(CustomAction::Unicode('é'))} {Custom(CustomAction::Unicode('→'))} {Custom(CustomAction::Unicode('λ'))} n ]
+        [ n {ms(MouseAction::Button(buttons::LEFT))} {ms(MouseAction::Button(buttons::RIGHT))} PgDown {ms(MouseAction::Left)} {ms(MouseAction::Down)} {ms(MouseAction::Right)}       n Left Down Up Right {media(MediaKey::BrightnessDown)} {media(MediaKey::BrightnessUp)} ]
+        [ n Undo Cut Copy Paste Again {ms(MouseAction::WheelDown)}       n {media(MediaKey::Mute)} {media(MediaKey::VolumeDown)} {media(MediaKey::VolumeUp)} {media(MediaKey::PlayPause)} {media(MediaKey::PrevTrack)} {media(MediaKey::NextTrack)} ]
         [ n n n t t t t            t t t t n n n ]
     }
     {
-        [ n n n n n n n            n n F7 F8 F9 F10 n ]
-        [ n n n n n n n            n n F4 F5 F6 F11 n ]
-        [ n n n n n n n            n n F1 F2 F3 F12 n ]
+        [ n {DefaultLayer(QWERTY)} {DefaultLayer(COLEMAK)} {DefaultLayer(DVORAK)} {Custom(CustomAction::Reset)} {Custom(CustomAction::Bootloader)} n            n {Custom(CustomAction::UnicodeInput(InputMethod::Linux))} F7 F8 F9 F10 {media(MediaKey::Sleep)} ]
+        [ n {Custom(CustomAction::AutoShift)} {Custom(CustomAction::RecordMacro(0))} {Custom(CustomAction::RecordMacro(1))} {Custom(CustomAction::RecordMacro(2))} {Custom(CustomAction::StopMacro)} {Custom(CustomAction::Nkro)}            n {Custom(CustomAction::UnicodeInput(InputMethod::MacOs))} F4 F5 F6 F11 n ]
+        [ n {Custom(CustomAction::ToggleLayer(2))} {Custom(CustomAction::Os(Os::Linux))} {Custom(CustomAction::Os(Os::MacOs))} {Custom(CustomAction::Os(Os::Windows))} n n            n {Custom(CustomAction::UnicodeInput(InputMethod::WinCompose))} F1 F2 F3 F12 n ]
         [ n n n t t t t            t t t t n n n ]
     }
+    {
+        [ Tab    Q W F P G n                    n J L U Y ; 0 ]
+        [ n      A R S T D Tab                  n H N E I O Quote ]
+        [ n      Z X C V B Escape           Enter K M , . / Escape ]
+        [ n n n LGui LShift BSpace LCtrl     RAlt Space (1) - n n n ]
+    }
+    {
+        [ Tab    Quote , . P Y n                n F G C R L 0 ]
+        [ n      A O E U I Tab                  n D H T N S / ]
+        [ n      ; Q J K X Escape               Enter B M W V Z Escape ]
+        [ n n n LGui LShift BSpace LCtrl     RAlt Space (1) - n n n ]
+    }
 };
 
+/// Layers 1 and 2 held: layer 3, whatever the order. Layer 3 held with
+/// any other: still layer 3, rather than a base layer.
+pub static CONDITIONAL_LAYERS: &[ConditionalLayer] = &[
+    ConditionalLayer {
+        if_all: &[1, 2],
+        then: 3,
+    },
+    ConditionalLayer {
+        if_all: &[3],
+        then: 3,
+    },
+];
+
+pub static KEY_OVERRIDES: &[KeyOverride] = &[
+    // Shift+Backspace: Delete, as on the base layers Delete has no key
+    KeyOverride::new(mods::SHIFT, BSpace, &[Delete]),
+];
+
+/// None: the keys of a combo are held back for its timeout, and keys
+/// rolled while typing would make it. See the tests for examples.
+pub static COMBOS: &[Combo] = &[];
+
+/// Off until toggled on layer 3, or enabled in the settings.
+pub static AUTO_SHIFT: AutoShift = AutoShift::new();
+
+/// The pointer speeding up linearly, precise at first then quick.
+pub static MOUSE_KEYS: MouseKeys = MouseKeys::new();
 
-// #[rustfmt::skip]
-// pub static LAYERS: keyberon::layout::Layers = &[
-//     &[
-//         &[k(Tab),     k(Q), k(W),  k(E),    k(R), k(T),    k(Y),     k(U),    k(I),   k(O),    k(P),     k(LBracket)],
-//         &[k(RBracket),k(A), k(S),  k(D),    k(F), k(G),    k(H),     k(J),    k(K),   k(L),    k(SColon),k(Quote)   ],
-//         &[k(Equal),   k(Z), k(X),  k(C),    k(V), k(B),    k(N),     k(M),    k(Comma),k(Dot), k(Slash), k(Bslash)  ],
-//         &[Trans,      Trans,k(LGui),k(LAlt),L1_SP,k(LCtrl),k(RShift),L2_ENTER,k(RAlt),k(BSpace),Trans,   Trans      ],
-//     ], &[
-//         &[Trans,         k(Pause),Trans,     k(PScreen),Trans,    Trans,Trans,      Trans,  k(Delete),Trans,  Trans,   Trans ],
-//         &[Trans,         Trans,   k(NumLock),k(Insert), k(Escape),Trans,k(CapsLock),k(Left),k(Down),  k(Up),  k(Right),Trans ],
-//         &[k(NonUsBslash),k(Undo), CUT,       COPY,      PASTE,    Trans,Trans,      k(Home),k(PgDown),k(PgUp),k(End),  Trans ],
-//         &[Trans,         Trans,   Trans,     Trans,     Trans,    Trans,Trans,      Trans,  Trans,    Trans,  Trans,   Trans ],
-//     ], &[
-//         &[s!(Grave),s!(Kb1),s!(Kb2),s!(Kb3),s!(Kb4),s!(Kb5),s!(Kb6),s!(Kb7),s!(Kb8),s!(Kb9),s!(Kb0),s!(Minus)],
-//         &[ k(Grave), k(Kb1), k(Kb2), k(Kb3), k(Kb4), k(Kb5), k(Kb6), k(Kb7), k(Kb8), k(Kb9), k(Kb0), k(Minus)],
-//         &[a!(Grave),a!(Kb1),a!(Kb2),a!(Kb3),a!(Kb4),a!(Kb5),a!(Kb6),a!(Kb7),a!(Kb8),a!(Kb9),a!(Kb0),a!(Minus)],
-//         &[Trans,    Trans,  Trans,  Trans,  CSPACE, Trans,  Trans,  Trans,  Trans,  Trans,  Trans,  Trans    ],
-//     ], &[
-//         &[k(F1),k(F2),k(F3),k(F4),k(F5),k(F6),k(F7),k(F8),k(F9),k(F10),k(F11),k(F12)],
-//         &[Trans,Trans,Trans,Trans,Trans,Trans,Trans,Trans,Trans,Trans, Trans, Trans ],
-//         &[Trans,Trans,Trans,Trans,Trans,Trans,Trans,Trans,Trans,Trans, Trans, Trans ],
-//         &[Trans,Trans,Trans,Trans,Trans,Trans,Trans,Trans,Trans,Trans, Trans, Trans ],
-//     ],
-// ];
-
-// const CUT: Action = m(&[LShift, Delete]);
-// const COPY: Action = m(&[LCtrl, Insert]);
-// const PASTE: Action = m(&[LShift, Insert]);
-// const L2_ENTER: Action = HoldTap {
-//     timeout: 140,
-//     hold: &l(2),
-//     tap: &k(Enter),
-//     config: keyberon::action::HoldTapConfig::Default,
-//     tap_hold_interval: 0
-// };
-// const L1_SP: Action = HoldTap {
-//     timeout: 200,
-//     hold: &l(1),
-//     tap: &k(Space),
-//     config: keyberon::action::HoldTapConfig::Default,
-//     tap_hold_interval: 0
-// };
-// const CSPACE: Action = m(&[LCtrl, Space]);
-// macro_rules! s {
-//     ($k:ident) => {
-//         m(&[LShift, $k])
-//     };
-// }
-// macro_rules! a {
-//     ($k:ident) => {
-//         m(&[RAlt, $k])
-//     };
-// }
+/// Key entering the bootloader when held while plugging in, whatever
+/// the keymap: Escape of the base layers, on the left half.
+pub static BOOT_KEY: (u8, u8) = (2, 6);
+
+/// What the LED shows, the first state first.
+pub static LED_STATES: &[LedState] = &[
+    LedState::Bootloader,
+    LedState::LinkLost,
+    LedState::CapsLock,
+    LedState::Layer,
+];
+
+static GIT_STATUS: SequenceMacro = SequenceMacro {
+    steps: &[Step::Text("git status\n")],
+};
+
+/// The message left between the quotes.
+static GIT_COMMIT: SequenceMacro = SequenceMacro {
+    steps: &[Step::Text("git commit -m \"\""), Step::Tap(&[Left])],
+};
+
+pub static LEADER: Leader = Leader {
+    sequences: &[
+        // Save
+        Sequence {
+            keys: &[S],
+            action: MultipleKeyCodes(&[LCtrl, S]),
+        },
+        // Quit
+        Sequence {
+            keys: &[Q],
+            action: MultipleKeyCodes(&[LAlt, F4]),
+        },
+        // Reopen the last tab closed
+        Sequence {
+            keys: &[T],
+            action: MultipleKeyCodes(&[LCtrl, LShift, T]),
+        },
+        // Ctrl+Alt+Delete
+        Sequence {
+            keys: &[D, E, L],
+            action: MultipleKeyCodes(&[LCtrl, LAlt, Delete]),
+        },
+        Sequence {
+            keys: &[G, S],
+            action: Custom(CustomAction::SequenceMacro(&GIT_STATUS)),
+        },
+        Sequence {
+            keys: &[G, C],
+            action: Custom(CustomAction::SequenceMacro(&GIT_COMMIT)),
+        },
+    ],
+    timeout: 1000,
+};
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use keyberon::{
-        key_code::KeyCode,
-        layout::{Event, Layout}
+    use crate::{dimensions::KEYS, leader::Trie, lint::{lint, Severity}, scenario::Scenario};
+    use crate::action::{HoldTap, OneShot, TapDance};
+
+    const fn ht(ht: &'static HoldTap) -> Action {
+        Custom(CustomAction::HoldTap(ht))
+    }
+
+    const fn td(dance: &'static TapDance) -> Action {
+        Custom(CustomAction::TapDance(dance))
+    }
+
+    const fn os(one_shot: &'static OneShot) -> Action {
+        Custom(CustomAction::OneShot(one_shot))
+    }
+
+    // Home-row modifiers
+    const A_GUI: HoldTap = HoldTap::home_row_mod(LGui, A);
+    const S_ALT: HoldTap = HoldTap::home_row_mod(LAlt, S);
+    const D_CTRL: HoldTap = HoldTap::home_row_mod(LCtrl, D);
+    const F_SHIFT: HoldTap = HoldTap {
+        timeout: 150,
+        ..HoldTap::home_row_mod(LShift, F)
+    };
+    const J_SHIFT: HoldTap = HoldTap {
+        timeout: 150,
+        ..HoldTap::home_row_mod(RShift, J)
+    };
+    const K_CTRL: HoldTap = HoldTap::home_row_mod(RCtrl, K);
+    const L_ALT: HoldTap = HoldTap::home_row_mod(LAlt, L);
+
+    // ; once, : twice, layer 3 when held
+    const SEMICOLON_L3: TapDance = TapDance {
+        taps: &[KeyCode(SColon), MultipleKeyCodes(&[LShift, SColon])],
+        holds: &[Layer(3)],
+        timeout: 0,
+    };
+
+    // One-shot shift and layer 1 on the thumbs
+    const OS_SHIFT: OneShot = OneShot::new(KeyCode(LShift));
+    const OS_L1: OneShot = OneShot::new(Layer(1));
+
+    // - when tapped, layer 2 when held
+    const MINUS_L2: HoldTap = HoldTap {
+        hold_on_other_key_press: true,
+        ..HoldTap::new(Layer(2), KeyCode(Minus))
     };
 
+    /// QWERTY with the extras left out of the keymap, over its layers 1
+    /// to 3 reduced to a key each.
+    static DEMO: Layers = layout! {
+        {
+            [ Tab    Q W E R T n                    n Y U I O P 0 ]
+            [ n {ht(&A_GUI)} {ht(&S_ALT)} {ht(&D_CTRL)} {ht(&F_SHIFT)} G Tab   n H {ht(&J_SHIFT)} {ht(&K_CTRL)} {ht(&L_ALT)} {td(&SEMICOLON_L3)} Quote ]
+            [ n      Z X C V B Escape           Enter N M , . / n ]
+            [ n n n LGui {os(&OS_SHIFT)} BSpace LCtrl     RAlt Space {os(&OS_L1)} {ht(&MINUS_L2)} n n n ]
+        }
+        {
+            [ t      t t t t t n                    n t Kb7 t t t t ]
+            [ n      t t t t t t                    n t t t t t t ]
+            [ n      t t t t t t                    t t t t t t t ]
+            [ n n n t t t t            t t t t n n n ]
+        }
+        {
+            [ t      t t t t t n                    n t PgUp t t t t ]
+            [ n      t t t t t t                    n t t t t t t ]
+            [ n      t t t t t t                    t t t t t t t ]
+            [ n n n t t t t            t t t t n n n ]
+        }
+        {
+            [ t      t t t t t n                    n t F7 t t t t ]
+            [ n      t t t t t t                    n t t t t t t ]
+            [ n      t t t t t t                    t t t t t t t ]
+            [ n n n t t t t            t t t t n n n ]
+        }
+    };
+
+    static DEMO_COMBOS: &[Combo] = &[
+        // J+K
+        Combo::new(&[(1, 9), (1, 10)], KeyCode(Escape)),
+        // Backspace+Space
+        Combo {
+            timeout: 50,
+            ..Combo::new(&[(3, 5), (3, 8)], Layer(3))
+        },
+    ];
+
+    fn keymap() -> Scenario {
+        Scenario::new(LAYERS)
+            .base_layers(BASE_LAYERS)
+            .combos(COMBOS)
+            .conditional_layers(CONDITIONAL_LAYERS)
+            .key_overrides(KEY_OVERRIDES)
+            .leader(&LEADER)
+            .auto_shift(&AUTO_SHIFT)
+            .mouse_keys(&MOUSE_KEYS)
+    }
+
+    fn demo() -> Scenario {
+        Scenario::new(DEMO)
+            .combos(DEMO_COMBOS)
+            .conditional_layers(CONDITIONAL_LAYERS)
+    }
+
+    #[test]
+    fn test_lint() {
+        lint(LAYERS, BASE_LAYERS, COMBOS, CONDITIONAL_LAYERS, &KEYS, |l| assert!(l.severity() < Severity::Error, "{}", l));
+        assert_eq!(Trie::new(LEADER.sequences).1, Ok(()));
+    }
+
+    #[test]
+    fn test_tap() {
+        keymap()
+            .press(0, 11) // O
+            .expect(&[O])
+            .tick(50)
+            .expect(&[O])
+            .release(0, 11)
+            .expect(&[])
+            .golden("tap");
+    }
+
+    #[test]
+    fn test_modifier() {
+        keymap()
+            .press(3, 4) // LShift
+            .press(1, 11) // L
+            .expect(&[LShift, L])
+            .release(1, 11)
+            .expect(&[LShift])
+            .release(3, 4)
+            .expect(&[]);
+    }
+
+    #[test]
+    fn test_home_row_mods() {
+        // Held, then another key tapped: a modifier
+        demo()
+            .tick(500)
+            .press(1, 4) // F
+            .tap(1, 9) // J
+            .expect(&[LShift, J])
+            .tick(1)
+            .expect(&[LShift])
+            .release(1, 4)
+            .expect(&[]);
+        // Rolled while typing: letters
+        demo()
+            .tick(500)
+            .press(1, 3) // D
+            .press(1, 4) // F
+            .release(1, 3)
+            .expect(&[D, F])
+            .release(1, 4)
+            .expect(&[]);
+        // Held alone past its timeout
+        demo()
+            .tick(500)
+            .press(1, 2) // S
+            .tick(200)
+            .expect(&[LAlt])
+            .release(1, 2)
+            .expect(&[]);
+    }
+
+    #[test]
+    fn test_layers() {
+        keymap()
+            .press(3, 9) // (1)
+            .expect(&[])
+            .tap(0, 9) // 7 on layer 1, U on the base layer
+            .press(0, 9)
+            .expect(&[Kb7])
+            .release(0, 9)
+            .press(3, 5) // (1) on layer 1, so layer 2
+            .press(1, 9) // Down on layer 2
+            .expect(&[Down])
+            .release(1, 9)
+            .release(3, 5)
+            .press(3, 4) // (2) on layer 1, so layer 3
+            .press(0, 9)
+            .expect(&[F7])
+            .release(0, 9)
+            .release(3, 9)
+            .press(3, 9) // and layer 3 again, the other way round
+            .press(0, 9)
+            .expect(&[F7])
+            .release(0, 9)
+            .release(3, 9)
+            .release(3, 4)
+            .press(1, 9) // and J on the base layer
+            .expect(&[J])
+            .release(1, 9)
+            .expect(&[])
+            .golden("layers");
+    }
+
+    #[test]
+    fn test_conditional_layers() {
+        // - when tapped, and layer 2 when held, adding layer 1 gives layer 3
+        demo()
+            .tap(3, 10) // -
+            .expect(&[Minus])
+            .tick(1)
+            .press(3, 10) // L2, once another key is pressed
+            .press(0, 9) // PgUp on layer 2
+            .expect(&[PgUp])
+            .release(0, 9)
+            .press(3, 9) // (1), so layer 3
+            .press(0, 9) // F7 on layer 3
+            .expect(&[F7]);
+    }
+
+    #[test]
+    fn test_one_shot() {
+        demo()
+            .tap(3, 4) // Shift
+            .press(0, 11) // O
+            .expect(&[LShift, O])
+            .release(0, 11)
+            .expect(&[])
+            .tap(3, 9) // (1)
+            .press(0, 9) // 7 on layer 1
+            .expect(&[Kb7])
+            .release(0, 9)
+            .press(0, 9) // U on the base layer
+            .expect(&[U]);
+    }
+
+    #[test]
+    fn test_rolls() {
+        // J rolled into K types both, no combo holding them back
+        keymap()
+            .press(1, 9) // J
+            .press(1, 10) // K
+            .expect(&[J, K])
+            .release(1, 9)
+            .expect(&[K])
+            .release(1, 10)
+            .expect(&[]);
+        // and ; typed twice quickly, twice ;
+        keymap()
+            .press(1, 12) // ;
+            .expect(&[SColon])
+            .release(1, 12)
+            .expect(&[])
+            .press(1, 12)
+            .expect(&[SColon]);
+    }
+
+    #[test]
+    fn test_tap_dance() {
+        demo()
+            .tap(1, 12) // ;
+            .tick(200)
+            .expect(&[SColon])
+            .tick(100)
+            .tap(1, 12)
+            .tap(1, 12)
+            .tick(200)
+            .expect(&[LShift, SColon]);
+        // or layer 3 when it holds it
+        demo()
+            .press(1, 12)
+            .tick(200)
+            .press(0, 9) // F7 on layer 3
+            .expect(&[F7]);
+    }
+
+    #[test]
+    fn test_combos() {
+        demo()
+            .press(1, 9) // J
+            .press(1, 10) // K
+            .expect(&[Escape])
+            .release(1, 9)
+            .expect(&[])
+            .release(1, 10)
+            .tick(100)
+            .press(3, 5) // Backspace
+            .press(3, 8) // Space
+            .press(0, 9) // F7 on layer 3
+            .tick(1)
+            .expect(&[F7]);
+    }
+
+    #[test]
+    fn test_leader() {
+        keymap()
+            .press(3, 9) // (1)
+            .tap(2, 7) // Leader
+            .release(3, 9)
+            .tap(1, 3) // D
+            .tap(0, 3) // E
+            .press(1, 11) // L
+            .expect(&[LCtrl, LAlt, Delete])
+            .release(1, 11)
+            .expect(&[])
+            .golden("leader_keymap");
+    }
+
+    #[test]
+    fn test_caps_word() {
+        keymap()
+            .press(3, 9) // (1)
+            .tap(1, 6) // Caps word
+            .release(3, 9)
+            .press(1, 4) // F
+            .expect(&[F, LShift])
+            .release(1, 4)
+            .tap(0, 13) // 0
+            .press(3, 10) // -
+            .expect(&[Minus, LShift])
+            .release(3, 10)
+            .tap(3, 8) // Space
+            .press(0, 9) // U
+            .expect(&[U])
+            .golden("caps_word_keymap");
+    }
+
+    #[test]
+    fn test_auto_shift() {
+        keymap()
+            .press(0, 9) // U
+            .tick(200)
+            .expect(&[U])
+            .release(0, 9)
+            .press(3, 9) // (1)
+            .press(3, 4) // (2) on layer 1, so layer 3
+            .tap(1, 1) // Auto-shift
+            .release(3, 4)
+            .release(3, 9)
+            .press(0, 9)
+            .tick(200)
+            .expect(&[U, LShift])
+            .release(0, 9)
+            .tap(0, 9)
+            .expect(&[U])
+            .golden("auto_shift_keymap");
+    }
+
+    #[test]
+    fn test_layer_lock() {
+        keymap()
+            .press(3, 9) // (1)
+            .tap(2, 6) // Lock
+            .release(3, 9)
+            .tick(2000)
+            .press(0, 9) // 7, on layer 1 still
+            .expect(&[Kb7])
+            .release(0, 9)
+            .tap(2, 6) // Unlock
+            .press(0, 9)
+            .expect(&[U])
+            .golden("layer_lock_keymap");
+    }
+
+    #[test]
+    fn test_base_layers() {
+        keymap()
+            .press(3, 9) // (1)
+            .press(3, 4) // (2) on layer 1, so layer 3
+            .tap(0, 2) // Colemak
+            .release(3, 4)
+            .release(3, 9)
+            .press(0, 3) // F
+            .expect(&[F])
+            .release(0, 3)
+            .press(3, 9) // Layer 1, shared
+            .press(0, 9)
+            .expect(&[Kb7])
+            .release(0, 9)
+            .press(3, 4) // and layer 3
+            .tap(0, 3) // Dvorak
+            .release(3, 4)
+            .release(3, 9)
+            .press(0, 3)
+            .expect(&[Dot])
+            .golden("base_layers");
+    }
+
+    #[test]
+    fn test_os() {
+        keymap()
+            .press(3, 9) // (1)
+            .press(3, 5) // (1) on layer 1, so layer 2
+            .press(2, 4) // Paste
+            .expect(&[LShift, Insert])
+            .release(2, 4)
+            .release(3, 5)
+            .press(3, 4) // (2) on layer 1, so layer 3
+            .tap(2, 3) // macOS
+            .release(3, 4)
+            .press(3, 5) // layer 2 again
+            .press(2, 4)
+            .expect(&[LGui, V])
+            .release(2, 4)
+            .release(3, 5)
+            .release(3, 9)
+            .press(3, 6) // Ctrl
+            .expect(&[LGui])
+            .press(0, 9)
+            .expect(&[LGui, U]);
+    }
+
+    #[test]
+    fn test_key_overrides() {
+        keymap()
+            .press(3, 4) // Shift
+            .press(3, 5) // Backspace
+            .expect(&[Delete])
+            .release(3, 4)
+            .expect(&[BSpace]);
+    }
+
+    #[test]
+    fn test_dynamic_macro() {
+        keymap()
+            .press(3, 9) // (1)
+            .press(3, 4) // (2) on layer 1, so layer 3
+            .tap(1, 3) // Record in slot 1
+            .release(3, 4)
+            .release(3, 9)
+            .tick(200)
+            .tap(0, 9) // U
+            .tick(20)
+            .tap(0, 11) // O
+            .press(3, 9)
+            .press(3, 4)
+            .tap(1, 5) // Stop
+            .release(3, 4)
+            .press(3, 5) // (1) on layer 1, so layer 2
+            .press(0, 2) // Play slot 1
+            .expect(&[U])
+            .tick(1)
+            .expect(&[])
+            .tick(20)
+            .expect(&[])
+            .tick(1)
+            .expect(&[O])
+            .release(0, 2)
+            .release(3, 5)
+            .release(3, 9)
+            .expect(&[])
+            .golden("layers_dynamic_macro");
+    }
+
     #[test]
-    fn test() {
-        let mut layout = Layout::new(LAYERS);
-
-        println!("press/release O");
-        layout.event(Event::Press(0, 9)); // the O key
-        layout.tick();
-        println!("{:?}", layout.keycodes().collect::<Vec<_>>());
-        layout.event(Event::Release(0, 9)); // the O key
-        layout.tick();
-        println!("{:?}", layout.keycodes().collect::<Vec<_>>());
-
-        println!("press (1)");
-        layout.event(Event::Press(3, 9)); // the (1) key
-        layout.tick();
-        println!("{:?}", layout.keycodes().collect::<Vec<_>>());
-
-        println!("press/release 7");
-        layout.event(Event::Press(0, 9)); // the 7 key
-        layout.tick();
-        println!("{:?}", layout.keycodes().collect::<Vec<_>>());
-        layout.event(Event::Release(0, 9)); // the O key
-        layout.tick();
-        println!("{:?}", layout.keycodes().collect::<Vec<_>>());
-
-
-        println!("press (2)");
-        layout.event(Event::Press(3, 5)); // the (2) key
-        layout.tick();
-        println!("{:?}", layout.keycodes().collect::<Vec<_>>());
-
-        println!("press/release L");
-        layout.event(Event::Press(1, 9)); // the L key
-        layout.tick();
-        println!("{:?}", layout.keycodes().collect::<Vec<_>>());
-        layout.event(Event::Release(1, 9)); // the L key
-        layout.tick();
-        println!("{:?}", layout.keycodes().collect::<Vec<_>>());
-
-        // println!("release (2)");
-        // layout.event(Event::Release(3, 5)); // the (2) key
-        // layout.tick();
-        // println!("{:?}", layout.keycodes().collect::<Vec<_>>());
-
-        println!("release (1)");
-        layout.event(Event::Release(3, 9)); // the (1) key
-        layout.tick();
-        println!("{:?}", layout.keycodes().collect::<Vec<_>>());
-
-        println!("press/release L");
-        layout.event(Event::Press(1, 9)); // the L key
-        layout.tick();
-        println!("{:?}", layout.keycodes().collect::<Vec<_>>());
-        layout.event(Event::Release(1, 9)); // the L key
-        layout.tick();
-        println!("{:?}", layout.keycodes().collect::<Vec<_>>());
-    }
-}
\ No newline at end of file
+    fn test_sequence_macro() {
+        keymap()
+            .press(3, 9) // (1)
+            .tap(2, 7) // Leader
+            .release(3, 9)
+            .tap(1, 5) // G
+            .tap(2, 3) // C
+            .tick(60)
+            .golden("layers_sequence_macro");
+    }
+
+    #[test]
+    fn test_unicode() {
+        keymap()
+            .press(3, 9) // (1)
+            .press(3, 4) // (2) on layer 1, so layer 3
+            .tap(2, 8) // WinCompose
+            .release(3, 4)
+            .press(3, 5) // (1) on layer 1, so layer 2
+            .press(0, 12) // λ
+            .tick(10)
+            .golden("layers_unicode");
+    }
+
+    #[test]
+    fn test_mouse_keys() {
+        keymap()
+            .press(3, 9) // (1)
+            .press(3, 5) // (1) on layer 1, so layer 2
+            .press(1, 6) // Right
+            .tick(99)
+            .release(1, 6)
+            .tap(1, 1) // Left button
+            .release(3, 5)
+            .release(3, 9)
+            .tick(10)
+            .expect_pointer(28, 0)
+            .golden("layers_mouse");
+    }
+
+    #[test]
+    fn test_media_keys() {
+        keymap()
+            .press(3, 9) // (1)
+            .press(3, 5) // (1) on layer 1, so layer 2
+            .tap(2, 10) // Volume up
+            .tap(2, 11) // Play/pause
+            .release(3, 5)
+            .release(3, 9)
+            .golden("layers_media");
+    }
+
+    #[test]
+    fn test_nkro() {
+        keymap()
+            .press(3, 9) // (1)
+            .press(3, 4) // (2) on layer 1, so layer 3
+            .tap(1, 6) // NKRO
+            .release(3, 4)
+            .release(3, 9)
+            .press(0, 1) // Q
+            .press(0, 2) // W
+            .press(0, 3) // E
+            .press(0, 4) // R
+            .press(0, 5) // T
+            .press(0, 8) // Y
+            .press(0, 9) // U
+            .expect(&[Q, W, E, R, T, Y, U])
+            .expect_boot(Default::default());
+    }
+}
@@ -0,0 +1,279 @@
+//! Leader key sequences.
+//!
+//! After the leader key, the keys typed are not sent but matched against
+//! the sequences. A complete sequence taps its action. A key that leads
+//! to no sequence aborts, as does the timeout, unless the keys typed so
+//! far are a complete sequence.
+
+use crate::action::Action;
+use arrayvec::ArrayVec;
+use keyberon::key_code::KeyCode;
+
+/// Maximum number of nodes of the trie, that is of keys of the
+/// sequences without their common prefixes.
+pub const MAX_NODES: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sequence {
+    pub keys: &'static [KeyCode],
+    pub action: Action,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Leader {
+    pub sequences: &'static [Sequence],
+    /// Time allowed between two keys of a sequence, in ms.
+    pub timeout: u16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The sequences don't fit in the trie.
+    Full,
+    /// The sequence is empty, or has the keys of a previous one.
+    Duplicate { sequence: usize },
+}
+
+#[derive(Clone, Copy)]
+struct Node {
+    key: KeyCode,
+    action: Option<&'static Action>,
+    child: Option<u8>,
+    sibling: Option<u8>,
+}
+
+/// The sequences, sharing their common prefixes. Node 0 is the root,
+/// before any key is typed.
+pub struct Trie {
+    nodes: ArrayVec<Node, MAX_NODES>,
+}
+
+impl Trie {
+    /// Builds the trie of `sequences`. On error, the trie holds the
+    /// sequences before the faulty one.
+    pub fn new(sequences: &'static [Sequence]) -> (Self, Result<(), Error>) {
+        let mut trie = Trie {
+            nodes: ArrayVec::new(),
+        };
+        trie.nodes.push(Node {
+            key: KeyCode::No,
+            action: None,
+            child: None,
+            sibling: None,
+        });
+        for (i, sequence) in sequences.iter().enumerate() {
+            if let Err(e) = trie.insert(i, sequence) {
+                return (trie, Err(e));
+            }
+        }
+        (trie, Ok(()))
+    }
+
+    fn insert(&mut self, i: usize, sequence: &'static Sequence) -> Result<(), Error> {
+        let mut node = 0;
+        let mut shared = 0;
+        while let Some(child) = sequence.keys.get(shared).and_then(|&k| self.child(node, k)) {
+            node = child;
+            shared += 1;
+        }
+        if self.nodes.len() + sequence.keys.len() - shared > MAX_NODES {
+            return Err(Error::Full);
+        }
+        for &key in &sequence.keys[shared..] {
+            let child = self.nodes.len();
+            self.nodes.push(Node {
+                key,
+                action: None,
+                child: None,
+                sibling: self.nodes[node].child,
+            });
+            self.nodes[node].child = Some(child as u8);
+            node = child;
+        }
+        match &mut self.nodes[node].action {
+            Some(_) => return Err(Error::Duplicate { sequence: i }),
+            _ if node == 0 => return Err(Error::Duplicate { sequence: i }),
+            action => *action = Some(&sequence.action),
+        }
+        Ok(())
+    }
+
+    /// The node after `node` when typing `key`, if any.
+    pub fn child(&self, node: usize, key: KeyCode) -> Option<usize> {
+        let mut child = self.nodes.get(node)?.child;
+        while let Some(c) = child {
+            let n = &self.nodes[c as usize];
+            if n.key == key {
+                return Some(c as usize);
+            }
+            child = n.sibling;
+        }
+        None
+    }
+
+    /// The action of the sequence ending at `node`, if any.
+    pub fn action(&self, node: usize) -> Option<&'static Action> {
+        self.nodes.get(node)?.action
+    }
+
+    /// Whether longer sequences start with the keys leading to `node`.
+    pub fn has_children(&self, node: usize) -> bool {
+        self.nodes.get(node).is_some_and(|n| n.child.is_some())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::action::{CustomAction, Layers};
+    use crate::scenario::Scenario;
+    use keyberon::action::Action::*;
+    use keyberon::key_code::KeyCode::*;
+    use keyberon_macros::layout;
+
+    static LAYERS: Layers = layout! {
+        { [{Custom(CustomAction::Leader)} A B C] [LShift (1) D E] }
+        { [t Kb1 Kb2 Kb3] [t t t t] }
+    };
+
+    static LEADER: Leader = Leader {
+        sequences: &[
+            Sequence {
+                keys: &[A],
+                action: MultipleKeyCodes(&[LCtrl, A]),
+            },
+            Sequence {
+                keys: &[A, B],
+                action: KeyCode(Escape),
+            },
+            Sequence {
+                keys: &[B, Kb1, C],
+                action: KeyCode(Enter),
+            },
+        ],
+        timeout: 100,
+    };
+
+    fn scenario() -> Scenario {
+        Scenario::new(LAYERS).leader(&LEADER)
+    }
+
+    #[test]
+    fn test_trie() {
+        let (trie, result) = Trie::new(LEADER.sequences);
+        assert_eq!(result, Ok(()));
+        let a = trie.child(0, A).unwrap();
+        assert_eq!(trie.action(a), Some(&LEADER.sequences[0].action));
+        assert!(trie.has_children(a));
+        let ab = trie.child(a, B).unwrap();
+        assert!(!trie.has_children(ab));
+        assert_eq!(trie.child(0, C), None);
+        assert_eq!(trie.action(trie.child(0, B).unwrap()), None);
+
+        static DUPLICATE: &[Sequence] = &[
+            Sequence {
+                keys: &[A, B],
+                action: NoOp,
+            },
+            Sequence {
+                keys: &[A, B],
+                action: Trans,
+            },
+        ];
+        let (trie, result) = Trie::new(DUPLICATE);
+        assert_eq!(result, Err(Error::Duplicate { sequence: 1 }));
+        assert_eq!(trie.action(2), Some(&NoOp));
+
+        static LONG: &[Sequence] = &[Sequence {
+            keys: &[A; MAX_NODES],
+            action: NoOp,
+        }];
+        assert_eq!(Trie::new(LONG).1, Err(Error::Full));
+    }
+
+    #[test]
+    fn test_sequence() {
+        scenario()
+            .tap(0, 0)
+            .tap(0, 1)
+            .expect(&[])
+            .press(0, 2)
+            .expect(&[Escape])
+            .release(0, 2)
+            .expect(&[])
+            .press(0, 2)
+            .expect(&[B]);
+        // with a layer key
+        scenario()
+            .tap(0, 0)
+            .tap(0, 2)
+            .press(1, 1)
+            .tap(0, 1)
+            .release(1, 1)
+            .press(0, 3)
+            .expect(&[Enter]);
+    }
+
+    #[test]
+    fn test_timeout() {
+        // The keys typed are a sequence
+        scenario()
+            .tap(0, 0)
+            .tap(0, 1)
+            .tick(98)
+            .expect(&[])
+            .tick(1)
+            .expect(&[LCtrl, A]);
+        // or not
+        scenario()
+            .tap(0, 0)
+            .tap(0, 2)
+            .tick(200)
+            .expect(&[])
+            .press(0, 1)
+            .expect(&[A]);
+    }
+
+    #[test]
+    fn test_abort() {
+        scenario()
+            .tap(0, 0)
+            .tap(0, 2)
+            .press(1, 0)
+            .tap(0, 3)
+            .release(1, 0)
+            .expect(&[])
+            .press(0, 3)
+            .expect(&[C]);
+    }
+
+    #[test]
+    fn test_after_one_shots() {
+        use crate::action::OneShot;
+        static SHIFT: OneShot = OneShot::new(KeyCode(LShift));
+        static CTRL: OneShot = OneShot::new(KeyCode(LCtrl));
+        static ALT: OneShot = OneShot::new(KeyCode(LAlt));
+        static GUI: OneShot = OneShot::new(KeyCode(LGui));
+        static LAYERS: Layers = layout! {
+            { [{Custom(CustomAction::Leader)} A B] [
+                {Custom(CustomAction::OneShot(&SHIFT))}
+                {Custom(CustomAction::OneShot(&CTRL))}
+                {Custom(CustomAction::OneShot(&ALT))}
+                {Custom(CustomAction::OneShot(&GUI))}
+            ] }
+        };
+        // The leader key is released along the one-shot keys
+        Scenario::new(LAYERS)
+            .leader(&LEADER)
+            .tap(1, 0)
+            .tap(1, 1)
+            .tap(1, 2)
+            .tap(1, 3)
+            .tap(0, 0)
+            .tap(0, 1)
+            .press(0, 2)
+            .expect(&[LShift, LCtrl, LAlt, LGui, Escape])
+            .tick(1)
+            .expect(&[]);
+    }
+}
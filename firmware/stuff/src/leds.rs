@@ -0,0 +1,310 @@
+//! The status LED: the lock state set by the host, and the patterns the
+//! only LED of the board shows.
+//!
+//! The LED shows the pattern of the first of its states that holds, in
+//! the order of the `LedState` list chosen, and is off when none does.
+//! `StatusLed` tells at each ms whether the LED is lit, a pattern
+//! starting over from its beginning when it changes.
+
+use keyberon::keyboard::Leds;
+
+/// Time without a frame of the other half after which the link is
+/// lost, in ms. The other half sends one per ms.
+pub const LINK_TIMEOUT_MS: u16 = 50;
+
+/// Half period of the blinks of the blink codes, in ms.
+pub const CODE_BLINK_MS: u32 = 200;
+
+/// Pause between two blink codes, in ms.
+pub const CODE_PAUSE_MS: u32 = 1000;
+
+/// Period of the software PWM of the breathing, in ms, and thus its
+/// number of brightness levels.
+pub const PWM_PERIOD_MS: u32 = 10;
+
+/// The lock state of the host, as set by its LED output reports.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HostLeds {
+    pub num_lock: bool,
+    pub caps_lock: bool,
+    pub scroll_lock: bool,
+    pub compose: bool,
+    pub kana: bool,
+}
+
+impl Leds for HostLeds {
+    fn num_lock(&mut self, status: bool) {
+        self.num_lock = status;
+    }
+    fn caps_lock(&mut self, status: bool) {
+        self.caps_lock = status;
+    }
+    fn scroll_lock(&mut self, status: bool) {
+        self.scroll_lock = status;
+    }
+    fn compose(&mut self, status: bool) {
+        self.compose = status;
+    }
+    fn kana(&mut self, status: bool) {
+        self.kana = status;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pattern {
+    Off,
+    On,
+    /// Lit for `on` ms, then off for `off` ms.
+    Blink {
+        on: u16,
+        off: u16,
+    },
+    /// `n` blinks, then a pause.
+    Code(u8),
+    /// Fading in then out, over `period` ms.
+    Breathe {
+        period: u16,
+    },
+}
+
+impl Pattern {
+    /// Whether the LED is lit `t` ms after the start of the pattern.
+    pub fn is_on(self, t: u32) -> bool {
+        match self {
+            Pattern::Off => false,
+            Pattern::On => true,
+            Pattern::Blink { on, off } => t % (on as u32 + off as u32).max(1) < on as u32,
+            Pattern::Code(_) => {
+                let t = t % (self.len() + CODE_PAUSE_MS);
+                t < self.len() && t % (2 * CODE_BLINK_MS) < CODE_BLINK_MS
+            }
+            Pattern::Breathe { period } => {
+                let half = (period as u32 / 2).max(1);
+                // Of the same duty cycle over a PWM period
+                let t2 = (t - t % PWM_PERIOD_MS) % (2 * half);
+                let level = if t2 < half { t2 } else { 2 * half - t2 } * PWM_PERIOD_MS / half;
+                t % PWM_PERIOD_MS < level
+            }
+        }
+    }
+
+    /// Length of the blinks of a blink code, without the pause, in ms.
+    fn len(self) -> u32 {
+        match self {
+            Pattern::Code(n) => 2 * n as u32 * CODE_BLINK_MS,
+            _ => 0,
+        }
+    }
+}
+
+/// A state the LED shows, when it holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LedState {
+    /// Breathing while the bootloader is about to be entered.
+    Bootloader,
+    /// Blinking quickly while the link to the other half is lost.
+    LinkLost,
+    /// Lit while Caps Lock is on.
+    CapsLock,
+    /// The number of the current layer as a blink code, while it isn't
+    /// the default one.
+    Layer,
+}
+
+/// What the LED may show.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Status {
+    pub host: HostLeds,
+    pub layer: usize,
+    pub default_layer: usize,
+    /// Time since the last frame of the other half, in ms.
+    pub since_frame: u16,
+    /// Whether the bootloader is about to be entered.
+    pub bootloader: bool,
+}
+
+impl LedState {
+    /// The pattern of the state, if it holds.
+    pub fn pattern(self, status: &Status) -> Option<Pattern> {
+        match self {
+            LedState::Bootloader if status.bootloader => Some(Pattern::Breathe { period: 1000 }),
+            LedState::LinkLost if status.since_frame >= LINK_TIMEOUT_MS => {
+                Some(Pattern::Blink { on: 100, off: 100 })
+            }
+            LedState::CapsLock if status.host.caps_lock => Some(Pattern::On),
+            LedState::Layer if status.layer != status.default_layer => {
+                Some(Pattern::Code(status.layer.min(u8::MAX as usize) as u8))
+            }
+            _ => None,
+        }
+    }
+}
+
+pub struct StatusLed {
+    states: &'static [LedState],
+    pattern: Pattern,
+    /// Time since the start of the pattern, in ms.
+    time: u32,
+    /// Whether a blink code of `new` or `show` is still shown.
+    code: bool,
+}
+
+impl StatusLed {
+    /// The LED blinks `startup` times, then shows the first of `states`
+    /// that holds.
+    pub fn new(states: &'static [LedState], startup: u8) -> Self {
+        StatusLed {
+            states,
+            pattern: Pattern::Code(startup),
+            time: 0,
+            code: true,
+        }
+    }
+
+    /// The LED blinks `code` times, then goes back to the states.
+    pub fn show(&mut self, code: u8) {
+        self.pattern = Pattern::Code(code);
+        self.time = 0;
+        self.code = true;
+    }
+
+    /// Ticks a ms, telling whether the LED is lit.
+    pub fn tick(&mut self, status: &Status) -> bool {
+        if self.code && self.time >= self.pattern.len() {
+            self.code = false;
+        }
+        if !self.code {
+            let pattern = self
+                .states
+                .iter()
+                .find_map(|s| s.pattern(status))
+                .unwrap_or(Pattern::Off);
+            if pattern != self.pattern {
+                self.pattern = pattern;
+                self.time = 0;
+            }
+        }
+        let lit = self.pattern.is_on(self.time);
+        self.time = self.time.wrapping_add(1);
+        lit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static STATES: &[LedState] = &[
+        LedState::Bootloader,
+        LedState::LinkLost,
+        LedState::CapsLock,
+        LedState::Layer,
+    ];
+
+    /// The LED over `ms` ms, a `#` for each 10 ms lit and a `.` for each
+    /// 10 ms off.
+    fn trace(led: &mut StatusLed, status: &Status, ms: u32) -> String {
+        (0..ms / 10)
+            .map(|_| match (0..10).filter(|_| led.tick(status)).count() {
+                10 => '#',
+                0 => '.',
+                _ => '~',
+            })
+            .collect()
+    }
+
+    fn lit(on: usize, off: usize) -> String {
+        "#".repeat(on) + &".".repeat(off)
+    }
+
+    #[test]
+    fn test_host_leds() {
+        let mut leds = HostLeds::default();
+        leds.caps_lock(true);
+        leds.num_lock(true);
+        leds.num_lock(false);
+        assert_eq!(
+            leds,
+            HostLeds {
+                caps_lock: true,
+                ..HostLeds::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_patterns() {
+        let blink = Pattern::Blink { on: 2, off: 3 };
+        let lit: Vec<_> = (0..6).map(|t| blink.is_on(t)).collect();
+        assert_eq!(lit, [true, true, false, false, false, true]);
+
+        let code = Pattern::Code(2);
+        let lit: Vec<_> = (0..5).map(|i| code.is_on(i * CODE_BLINK_MS)).collect();
+        assert_eq!(lit, [true, false, true, false, false]);
+        assert!(!code.is_on(4 * CODE_BLINK_MS + CODE_PAUSE_MS - 1));
+        assert!(code.is_on(4 * CODE_BLINK_MS + CODE_PAUSE_MS));
+
+        // The duty cycle goes up to full, then down
+        let breathe = Pattern::Breathe { period: 1000 };
+        let duty = |from: u32| {
+            (from..from + PWM_PERIOD_MS)
+                .filter(|&t| breathe.is_on(t))
+                .count()
+        };
+        assert_eq!(duty(0), 0);
+        assert_eq!(duty(250), 5);
+        assert_eq!(duty(500), 10);
+        assert_eq!(duty(750), 5);
+        assert_eq!(duty(1000), 0);
+    }
+
+    #[test]
+    fn test_priorities() {
+        let mut status = Status {
+            host: HostLeds {
+                caps_lock: true,
+                ..HostLeds::default()
+            },
+            layer: 2,
+            since_frame: LINK_TIMEOUT_MS,
+            bootloader: true,
+            ..Status::default()
+        };
+        let pattern = |status: &Status| STATES.iter().find_map(|s| s.pattern(status));
+        assert_eq!(pattern(&status), Some(Pattern::Breathe { period: 1000 }));
+        status.bootloader = false;
+        assert_eq!(pattern(&status), Some(Pattern::Blink { on: 100, off: 100 }));
+        status.since_frame = 0;
+        assert_eq!(pattern(&status), Some(Pattern::On));
+        status.host.caps_lock = false;
+        assert_eq!(pattern(&status), Some(Pattern::Code(2)));
+        status.default_layer = 2;
+        assert_eq!(pattern(&status), None);
+    }
+
+    #[test]
+    fn test_status_led() {
+        let mut led = StatusLed::new(STATES, 2);
+        let mut status = Status::default();
+        // The startup code, then off
+        assert_eq!(trace(&mut led, &status, 1000), lit(20, 20) + &lit(20, 40));
+        status.layer = 1;
+        assert_eq!(trace(&mut led, &status, 600), lit(20, 40));
+        // Starts over when shown again
+        status.host.caps_lock = true;
+        assert_eq!(trace(&mut led, &status, 100), lit(10, 0));
+        status.host.caps_lock = false;
+        assert_eq!(trace(&mut led, &status, 300), lit(20, 10));
+        // Off, then dimmed, lit in the middle, and dimmed again
+        status.bootloader = true;
+        let breathing = trace(&mut led, &status, 1000);
+        assert_eq!(
+            breathing,
+            lit(0, 5) + &"~".repeat(45) + "#" + &"~".repeat(45) + &lit(0, 4)
+        );
+        // A code shown over the states, once
+        led.show(1);
+        assert_eq!(trace(&mut led, &status, 400), lit(20, 20));
+        assert_eq!(trace(&mut led, &status, 100), lit(0, 5) + &"~".repeat(5));
+    }
+}
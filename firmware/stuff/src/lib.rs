@@ -1,6 +1,27 @@
 #![cfg_attr(not(test), no_std)]
 
+pub mod action;
+pub mod auto_shift;
 pub mod codec;
+pub mod combo;
+pub mod conditional;
 pub mod crc8;
 pub mod dimensions;
+pub mod dynamic_macro;
+pub mod key_override;
+pub mod keyboard;
 pub mod layers;
+pub mod leader;
+pub mod leds;
+pub mod lint;
+pub mod media;
+pub mod mouse;
+pub mod nkro;
+pub mod os;
+pub mod render;
+#[cfg(test)]
+pub mod scenario;
+pub mod sequence_macro;
+pub mod settings;
+pub mod storage;
+pub mod unicode;
@@ -0,0 +1,541 @@
+//! Static checks of a keymap.
+
+use crate::action::{Action, CustomAction, Layers};
+use crate::combo::Combo;
+use crate::conditional::{self, bit, ConditionalLayer};
+use core::fmt;
+use keyberon::key_code::KeyCode;
+
+/// Maximum number of layers checked.
+pub const MAX_LAYERS: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Warning => f.write_str("warning"),
+            Severity::Error => f.write_str("error"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lint {
+    /// No layer action leads to the layer.
+    UnreachableLayer { layer: usize },
+    /// A `Trans` on a base layer, where there is nothing below.
+    TransOnBase { layer: usize, coord: (u8, u8) },
+    /// A layer key that does something else on the layer it leads to.
+    ShadowedLayerKey {
+        layer: usize,
+        coord: (u8, u8),
+        target: usize,
+    },
+    /// The same keycode at two positions of a layer.
+    DuplicateKeyCode {
+        layer: usize,
+        keycode: KeyCode,
+        first: (u8, u8),
+        coord: (u8, u8),
+    },
+    /// A `NoOp` where a base layer has a key.
+    Hole { layer: usize, coord: (u8, u8) },
+    /// An action at a position without a physical key.
+    NoKey { layer: usize, coord: (u8, u8) },
+    /// A combo with a key at a position without a physical key.
+    ComboKey { combo: usize, coord: (u8, u8) },
+    /// Layers held at once adding up to a base layer.
+    SumOnBaseLayer { layer: usize },
+}
+
+impl Lint {
+    pub fn severity(&self) -> Severity {
+        match self {
+            Lint::UnreachableLayer { .. }
+            | Lint::TransOnBase { .. }
+            | Lint::NoKey { .. }
+            | Lint::ComboKey { .. }
+            | Lint::SumOnBaseLayer { .. } => Severity::Error,
+            _ => Severity::Warning,
+        }
+    }
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Lint::UnreachableLayer { layer } => write!(f, "layer {} can never be reached", layer),
+            Lint::TransOnBase { layer, coord } => {
+                write!(
+                    f,
+                    "transparent key at {:?} on the base layer {}",
+                    coord, layer
+                )
+            }
+            Lint::ShadowedLayerKey {
+                layer,
+                coord,
+                target,
+            } => write!(
+                f,
+                "layer key at {:?} on layer {} leads to layer {}, where it is another key",
+                coord, layer, target
+            ),
+            Lint::DuplicateKeyCode {
+                layer,
+                keycode,
+                first,
+                coord,
+            } => write!(
+                f,
+                "{:?} at {:?} and {:?} on layer {}",
+                keycode, first, coord, layer
+            ),
+            Lint::Hole { layer, coord } => write!(
+                f,
+                "no-op at {:?} on layer {} hides a key of a base layer",
+                coord, layer
+            ),
+            Lint::NoKey { layer, coord } => {
+                write!(
+                    f,
+                    "action at {:?} on layer {}, where there is no key",
+                    coord, layer
+                )
+            }
+            Lint::ComboKey { combo, coord } => {
+                write!(
+                    f,
+                    "combo {} has a key at {:?}, where there is no key",
+                    combo, coord
+                )
+            }
+            Lint::SumOnBaseLayer { layer } => write!(
+                f,
+                "layers held add up to layer {}, that is a base layer",
+                layer
+            ),
+        }
+    }
+}
+
+/// Checks `layers`, `combos` and `conditionals`, `keys` telling where
+/// there are physical keys. `base_layers` are the layers that can be the
+/// default one, under all the others.
+///
+/// Layers are reached as keyberon does: holding several layer keys
+/// activates the sum of their layers, unless they activate a conditional
+/// layer.
+pub fn lint<const W: usize>(
+    layers: Layers,
+    base_layers: &[usize],
+    combos: &[Combo],
+    conditionals: &[ConditionalLayer],
+    keys: &[[bool; W]],
+    mut report: impl FnMut(Lint),
+) {
+    let exists = |(i, j): (u8, u8)| {
+        keys.get(i as usize)
+            .and_then(|r| r.get(j as usize))
+            .copied()
+            .unwrap_or(false)
+    };
+    let (reached, sums) = reachable(layers, base_layers, combos, conditionals);
+    for (l, reached) in reached.iter().enumerate().take(layers.len()) {
+        if reached == &[false; 2] {
+            report(Lint::UnreachableLayer { layer: l });
+        }
+        if base_layers.contains(&l) && sums & bit(l) != 0 {
+            report(Lint::SumOnBaseLayer { layer: l });
+        }
+    }
+
+    for (l, layer) in layers.iter().enumerate() {
+        let base = base_layers.contains(&l);
+        for (i, row) in layer.iter().enumerate() {
+            for (j, action) in row.iter().enumerate() {
+                let coord = (i as u8, j as u8);
+                let exists = exists(coord);
+                match action {
+                    Action::NoOp => {
+                        let hides = |&b: &usize| !matches!(at(layers, b, b, coord), Action::NoOp);
+                        if !base && exists && base_layers.iter().any(hides) {
+                            report(Lint::Hole { layer: l, coord });
+                        }
+                        continue;
+                    }
+                    Action::Trans if base => report(Lint::TransOnBase { layer: l, coord }),
+                    Action::Trans => continue,
+                    _ if !exists => report(Lint::NoKey { layer: l, coord }),
+                    _ => (),
+                }
+                if let Action::KeyCode(kc) = action {
+                    let first = layer
+                        .iter()
+                        .enumerate()
+                        .flat_map(|(i, r)| {
+                            r.iter()
+                                .enumerate()
+                                .map(move |(j, a)| ((i as u8, j as u8), a))
+                        })
+                        .find(|(_, a)| matches!(a, Action::KeyCode(k) if k == kc))
+                        .map(|(c, _)| c);
+                    if let Some(first) = first.filter(|&f| f != coord) {
+                        report(Lint::DuplicateKeyCode {
+                            layer: l,
+                            keycode: *kc,
+                            first,
+                            coord,
+                        });
+                    }
+                }
+                if let (Action::Layer(n), Some(&[default, mom])) = (action, reached.get(l)) {
+                    if !default && !mom {
+                        continue;
+                    }
+                    let target = if mom { held(conditionals, l, *n) } else { *n };
+                    let shadowed = |&b: &usize| !matches!(at(layers, b, target, coord), Action::Layer(m) if m == n);
+                    if target < layers.len() && bases(base_layers, target).iter().any(shadowed) {
+                        report(Lint::ShadowedLayerKey {
+                            layer: l,
+                            coord,
+                            target,
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    for (c, combo) in combos.iter().enumerate() {
+        for &coord in combo.keys.iter().filter(|&&coord| !exists(coord)) {
+            report(Lint::ComboKey { combo: c, coord });
+        }
+    }
+}
+
+/// The base layers under `layer`: itself if it is one, else any of them.
+fn bases(base_layers: &[usize], layer: usize) -> &[usize] {
+    match base_layers.iter().position(|&b| b == layer) {
+        Some(i) => &base_layers[i..=i],
+        None => base_layers,
+    }
+}
+
+/// The action at `coord` of `layer`, `Trans` resolving to the base layer
+/// `base`.
+fn at(layers: Layers, base: usize, layer: usize, coord: (u8, u8)) -> &'static Action {
+    let action = layers
+        .get(layer)
+        .and_then(|l| l.get(coord.0 as usize))
+        .and_then(|r| r.get(coord.1 as usize));
+    match action {
+        Some(Action::Trans) if layer != base => at(layers, base, base, coord),
+        Some(action) => action,
+        None => &Action::NoOp,
+    }
+}
+
+/// The layer when holding layer `n` on the momentary layer `l`.
+fn held(conditionals: &[ConditionalLayer], l: usize, n: usize) -> usize {
+    conditional::resolve(conditionals, bit(l) | bit(n)).unwrap_or(l + n)
+}
+
+/// For each layer, whether it can be reached as default layer, and by
+/// holding layer keys, with the bit set of the layers reached by a sum
+/// of layers.
+fn reachable(
+    layers: Layers,
+    base_layers: &[usize],
+    combos: &[Combo],
+    conditionals: &[ConditionalLayer],
+) -> ([[bool; 2]; MAX_LAYERS], u32) {
+    let mut reached = [[false; 2]; MAX_LAYERS];
+    let mut sums = 0;
+    reached[0][0] = true;
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for l in 0..layers.len().min(MAX_LAYERS) {
+            let modes = reached[l];
+            for mom in [false, true].iter().filter(|&&m| modes[m as usize]) {
+                let combos = combos
+                    .iter()
+                    .filter(|c| c.enabled(l))
+                    .map(|c| (&c.action, false));
+                let keys = layers[l].iter().enumerate().flat_map(|(i, r)| {
+                    r.iter().enumerate().flat_map(move |(j, a)| {
+                        let trans = matches!(a, Action::Trans);
+                        let bases = bases(base_layers, l).iter();
+                        bases.map(move |&b| (at(layers, b, l, (i as u8, j as u8)), trans))
+                    })
+                });
+                for (action, trans) in keys.chain(combos) {
+                    for_each_target(action, &mut |target, momentary| {
+                        // Through a transparent key, a key of the layer
+                        // itself is the one held
+                        if trans && momentary && *mom && target == l {
+                            return;
+                        }
+                        let target = if momentary && *mom {
+                            let held = held(conditionals, l, target);
+                            if conditional::resolve(conditionals, bit(l) | bit(target)).is_none() {
+                                sums |= bit(held);
+                            }
+                            held
+                        } else {
+                            target
+                        };
+                        if target < MAX_LAYERS && !reached[target][momentary as usize] {
+                            reached[target][momentary as usize] = true;
+                            changed = true;
+                        }
+                    });
+                }
+            }
+        }
+        for c in conditionals.iter().filter(|c| c.then < MAX_LAYERS) {
+            let held = |&l: &usize| l < MAX_LAYERS && reached[l][1];
+            if c.if_all.iter().all(held) && !reached[c.then][1] {
+                reached[c.then][1] = true;
+                changed = true;
+            }
+        }
+    }
+    (reached, sums)
+}
+
+/// Calls `f` with each layer `action` leads to, and whether the layer
+/// is momentary.
+fn for_each_target(action: &Action, f: &mut impl FnMut(usize, bool)) {
+    match action {
+        Action::Layer(l) | Action::Custom(CustomAction::ToggleLayer(l)) => f(*l, true),
+        Action::DefaultLayer(l) => f(*l, false),
+        Action::HoldTap { hold, tap, .. } => {
+            for_each_target(hold, f);
+            for_each_target(tap, f);
+        }
+        Action::MultipleActions(actions) => actions.iter().for_each(|a| for_each_target(a, f)),
+        Action::Custom(CustomAction::HoldTap(ht)) => {
+            for_each_target(&ht.hold, f);
+            for_each_target(&ht.tap, f);
+        }
+        Action::Custom(CustomAction::OneShot(one_shot)) => for_each_target(&one_shot.action, f),
+        Action::Custom(CustomAction::TapDance(dance)) => {
+            let actions = dance.taps.iter().chain(dance.holds);
+            actions.for_each(|a| for_each_target(a, f));
+        }
+        _ => (),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::action::HoldTap;
+    use keyberon_macros::layout;
+
+    const KEYS: [[bool; 3]; 2] = [[true, true, true], [true, true, false]];
+
+    fn lints(layers: Layers) -> Vec<Lint> {
+        lints_with_combos(layers, &[])
+    }
+
+    fn lints_with_combos(layers: Layers, combos: &[Combo]) -> Vec<Lint> {
+        lints_with(layers, &[0], combos, &[])
+    }
+
+    fn lints_with(
+        layers: Layers,
+        base_layers: &[usize],
+        combos: &[Combo],
+        conditionals: &[ConditionalLayer],
+    ) -> Vec<Lint> {
+        let mut lints = vec![];
+        lint(layers, base_layers, combos, conditionals, &KEYS, |l| {
+            lints.push(l)
+        });
+        lints
+    }
+
+    #[test]
+    fn test_clean() {
+        static LAYERS: Layers = layout! {
+            { [A B C] [(1) D n] }
+            { [t E F] [t G n] }
+        };
+        assert_eq!(lints(LAYERS), vec![]);
+    }
+
+    #[test]
+    fn test_unreachable_and_trans() {
+        static LAYERS: Layers = layout! {
+            { [A t C] [(1) D n] }
+            { [t E F] [t G n] }
+            { [t E F] [t G n] }
+        };
+        assert_eq!(
+            lints(LAYERS),
+            vec![
+                Lint::UnreachableLayer { layer: 2 },
+                Lint::TransOnBase {
+                    layer: 0,
+                    coord: (0, 1)
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sum_of_layers() {
+        static LAYERS: Layers = layout! {
+            { [A B C] [(1) D n] }
+            { [t E F] [t (1) n] }
+            { [t E F] [t (1) n] }
+        };
+        assert_eq!(lints(LAYERS), vec![]);
+    }
+
+    #[test]
+    fn test_conditional_layers() {
+        static LAYERS: Layers = layout! {
+            { [A (1) (2)] [B C n] }
+            { [t t t] [D E n] }
+            { [t t t] [F G n] }
+            { [H I J] [K L n] }
+            { [M N O] [P Q n] }
+        };
+        static CONDITIONALS: &[ConditionalLayer] = &[ConditionalLayer {
+            if_all: &[1, 2],
+            then: 4,
+        }];
+        // Layers 1 and 2 held add up to layer 3, unless they activate
+        // layer 4
+        assert_eq!(lints(LAYERS), vec![Lint::UnreachableLayer { layer: 4 }]);
+        assert_eq!(
+            lints_with(LAYERS, &[0], &[], CONDITIONALS),
+            vec![Lint::UnreachableLayer { layer: 3 }]
+        );
+    }
+
+    #[test]
+    fn test_sum_on_base_layer() {
+        static LAYERS: Layers = layout! {
+            { [A (1) (2)] [B {Action::DefaultLayer(3)} n] }
+            { [t t t] [D E n] }
+            { [t t t] [F G n] }
+            { [H (1) (2)] [I J n] }
+        };
+        assert_eq!(
+            lints_with(LAYERS, &[0, 3], &[], &[]),
+            vec![Lint::SumOnBaseLayer { layer: 3 }]
+        );
+        static CONDITIONALS: &[ConditionalLayer] = &[ConditionalLayer {
+            if_all: &[1, 2],
+            then: 2,
+        }];
+        assert_eq!(lints_with(LAYERS, &[0, 3], &[], CONDITIONALS), vec![]);
+    }
+
+    #[test]
+    fn test_base_layers() {
+        static LAYERS: Layers = layout! {
+            { [A (1) n] [B {Action::DefaultLayer(2)} n] }
+            { [t t n] [t t n] }
+            { [C (1) D] [t {Action::DefaultLayer(0)} n] }
+        };
+        // Transparent keys are errors on every base layer, and no-ops
+        // hide the keys of any of them
+        assert_eq!(
+            lints_with(LAYERS, &[0, 2], &[], &[]),
+            vec![
+                Lint::Hole {
+                    layer: 1,
+                    coord: (0, 2)
+                },
+                Lint::TransOnBase {
+                    layer: 2,
+                    coord: (1, 0)
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_hold_tap() {
+        const SPACE: HoldTap = HoldTap::new(Action::Layer(1), Action::KeyCode(KeyCode::Space));
+        static LAYERS: Layers = layout! {
+            { [A B C] [{Action::Custom(CustomAction::HoldTap(&SPACE))} D n] }
+            { [t E F] [t G n] }
+        };
+        assert_eq!(lints(LAYERS), vec![]);
+    }
+
+    #[test]
+    fn test_combos() {
+        static LAYERS: Layers = layout! {
+            { [A B C] [D E n] }
+            { [t F G] [t t n] }
+        };
+        static COMBOS: &[Combo] = &[
+            Combo::new(&[(0, 0), (0, 1)], Action::Layer(1)),
+            Combo::new(&[(0, 2), (1, 2)], Action::KeyCode(KeyCode::Escape)),
+        ];
+        assert_eq!(
+            lints_with_combos(LAYERS, COMBOS),
+            vec![Lint::ComboKey {
+                combo: 1,
+                coord: (1, 2)
+            }]
+        );
+    }
+
+    #[test]
+    fn test_shadowed_layer_key() {
+        static LAYERS: Layers = layout! {
+            { [A B C] [(1) D n] }
+            { [t E F] [t (1) n] }
+            { [t E F] [t G n] }
+        };
+        assert_eq!(
+            lints(LAYERS),
+            vec![Lint::ShadowedLayerKey {
+                layer: 1,
+                coord: (1, 1),
+                target: 2
+            }]
+        );
+    }
+
+    #[test]
+    fn test_duplicates_holes_and_missing_keys() {
+        static LAYERS: Layers = layout! {
+            { [A B A] [(1) D n] }
+            { [t n F] [t G H] }
+        };
+        assert_eq!(
+            lints(LAYERS),
+            vec![
+                Lint::DuplicateKeyCode {
+                    layer: 0,
+                    keycode: KeyCode::A,
+                    first: (0, 0),
+                    coord: (0, 2)
+                },
+                Lint::Hole {
+                    layer: 1,
+                    coord: (0, 1)
+                },
+                Lint::NoKey {
+                    layer: 1,
+                    coord: (1, 2)
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,245 @@
+//! Media keys: consumer control (volume, playback, brightness) and system
+//! control (sleep, power) usages, sent on their own HID interface.
+//!
+//! Each control reports the usage of the last of its keys held, 0 when
+//! none. A report is sent only when the usage changes, and a key tapped
+//! between two reports is reported pressed, then released.
+
+/// Report ID of the consumer control reports.
+pub const CONSUMER_ID: u8 = 1;
+
+/// Report ID of the system control reports.
+pub const SYSTEM_ID: u8 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MediaKey {
+    Mute,
+    VolumeUp,
+    VolumeDown,
+    PlayPause,
+    Stop,
+    NextTrack,
+    PrevTrack,
+    BrightnessUp,
+    BrightnessDown,
+    Sleep,
+    Power,
+    Wake,
+}
+
+/// The usage of a media key, in the consumer or the generic desktop
+/// page.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Usage {
+    Consumer(u16),
+    System(u16),
+}
+
+impl MediaKey {
+    pub fn usage(self) -> Usage {
+        match self {
+            MediaKey::Mute => Usage::Consumer(0xe2),
+            MediaKey::VolumeUp => Usage::Consumer(0xe9),
+            MediaKey::VolumeDown => Usage::Consumer(0xea),
+            MediaKey::PlayPause => Usage::Consumer(0xcd),
+            MediaKey::Stop => Usage::Consumer(0xb7),
+            MediaKey::NextTrack => Usage::Consumer(0xb5),
+            MediaKey::PrevTrack => Usage::Consumer(0xb6),
+            MediaKey::BrightnessUp => Usage::Consumer(0x6f),
+            MediaKey::BrightnessDown => Usage::Consumer(0x70),
+            MediaKey::Power => Usage::System(0x81),
+            MediaKey::Sleep => Usage::System(0x82),
+            MediaKey::Wake => Usage::System(0x83),
+        }
+    }
+}
+
+/// A report of the media interface, with the usage held, 0 for none.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MediaReport {
+    Consumer(u16),
+    System(u16),
+}
+
+impl MediaReport {
+    /// The report ID, then the usage, little endian.
+    pub fn as_bytes(&self) -> [u8; 3] {
+        let (id, usage) = match *self {
+            MediaReport::Consumer(usage) => (CONSUMER_ID, usage),
+            MediaReport::System(usage) => (SYSTEM_ID, usage),
+        };
+        let [lo, hi] = usage.to_le_bytes();
+        [id, lo, hi]
+    }
+}
+
+/// The media keys held at a tick, as usages.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Held {
+    pub consumer: u16,
+    pub system: u16,
+}
+
+impl Held {
+    pub fn add(&mut self, key: MediaKey) {
+        match key.usage() {
+            Usage::Consumer(usage) => self.consumer = usage,
+            Usage::System(usage) => self.system = usage,
+        }
+    }
+}
+
+/// The state of a control.
+#[derive(Debug, Clone, Copy, Default)]
+struct Control {
+    held: u16,
+    /// Usage pressed since the last report sent, 0 for none.
+    pressed: u16,
+    /// Usage of the last report sent.
+    sent: u16,
+}
+
+impl Control {
+    fn tick(&mut self, held: u16) {
+        if held != 0 && held != self.held && held != self.sent {
+            self.pressed = held;
+        }
+        self.held = held;
+    }
+
+    /// The usage to report, if it changed: the one pressed since the last
+    /// report first, then the one held.
+    fn report(&self) -> Option<u16> {
+        let usage = if self.pressed != 0 {
+            self.pressed
+        } else {
+            self.held
+        };
+        (usage != self.sent).then_some(usage)
+    }
+
+    fn sent(&mut self, usage: u16) {
+        self.sent = usage;
+        self.pressed = 0;
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Media {
+    consumer: Control,
+    system: Control,
+}
+
+impl Media {
+    pub fn tick(&mut self, held: Held) {
+        self.consumer.tick(held.consumer);
+        self.system.tick(held.system);
+    }
+
+    /// The next report to send, the consumer control first, none if
+    /// neither control changed.
+    pub fn report(&self) -> Option<MediaReport> {
+        self.consumer
+            .report()
+            .map(MediaReport::Consumer)
+            .or_else(|| self.system.report().map(MediaReport::System))
+    }
+
+    /// Tells that `report` was sent.
+    pub fn report_sent(&mut self, report: &MediaReport) {
+        match *report {
+            MediaReport::Consumer(usage) => self.consumer.sent(usage),
+            MediaReport::System(usage) => self.system.sent(usage),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::action::{CustomAction, Layers};
+    use crate::scenario::Scenario;
+    use keyberon::action::Action::*;
+    use keyberon_macros::layout;
+
+    static LAYERS: Layers = layout! {
+        {[
+            {Custom(CustomAction::Media(MediaKey::VolumeUp))}
+            {Custom(CustomAction::Media(MediaKey::Mute))}
+            {Custom(CustomAction::Media(MediaKey::Sleep))}
+        ]}
+    };
+
+    fn held(consumer: u16, system: u16) -> Held {
+        Held { consumer, system }
+    }
+
+    #[test]
+    fn test_as_bytes() {
+        assert_eq!(
+            MediaReport::Consumer(0xe9).as_bytes(),
+            [CONSUMER_ID, 0xe9, 0]
+        );
+        assert_eq!(MediaReport::System(0x82).as_bytes(), [SYSTEM_ID, 0x82, 0]);
+    }
+
+    #[test]
+    fn test_changes() {
+        let mut media = Media::default();
+        media.tick(held(0xe9, 0x82));
+        let report = media.report().unwrap();
+        assert_eq!(report, MediaReport::Consumer(0xe9));
+        media.report_sent(&report);
+        let report = media.report().unwrap();
+        assert_eq!(report, MediaReport::System(0x82));
+        media.report_sent(&report);
+        // Still held: nothing to send
+        media.tick(held(0xe9, 0x82));
+        assert_eq!(media.report(), None);
+        media.tick(held(0, 0x82));
+        assert_eq!(media.report(), Some(MediaReport::Consumer(0)));
+    }
+
+    #[test]
+    fn test_tap_between_reports() {
+        let mut media = Media::default();
+        media.tick(held(0xe2, 0));
+        media.tick(held(0, 0));
+        let report = media.report().unwrap();
+        assert_eq!(report, MediaReport::Consumer(0xe2));
+        media.report_sent(&report);
+        assert_eq!(media.report(), Some(MediaReport::Consumer(0)));
+        // While another key is held
+        let mut media = Media::default();
+        media.tick(held(0xe9, 0));
+        media.report_sent(&MediaReport::Consumer(0xe9));
+        media.tick(held(0xe2, 0));
+        media.tick(held(0xe9, 0));
+        let report = media.report().unwrap();
+        assert_eq!(report, MediaReport::Consumer(0xe2));
+        media.report_sent(&report);
+        assert_eq!(media.report(), Some(MediaReport::Consumer(0xe9)));
+    }
+
+    #[test]
+    fn test_media_keys() {
+        Scenario::new(LAYERS)
+            .polling(8)
+            .press(0, 0)
+            .tick(20)
+            .expect_media(MediaReport::Consumer(0xe9))
+            // Tapped between two reads, sent all the same
+            .press(0, 1)
+            .release(0, 1)
+            .release(0, 0)
+            .tick(1)
+            .expect_media(MediaReport::Consumer(0xe2))
+            .tick(9)
+            .expect_media(MediaReport::Consumer(0))
+            .tap(0, 2)
+            .tick(5)
+            .expect_media(MediaReport::System(0x82))
+            .tick(15)
+            .expect_media(MediaReport::System(0));
+    }
+}
@@ -0,0 +1,395 @@
+//! Mouse keys: the pointer moved, the wheel scrolled and the buttons
+//! clicked with keys.
+//!
+//! At each tick, the movement keys held give a direction, and the
+//! acceleration curve the speed of each axis. The motion is counted in
+//! µpx so that slow speeds still move, and adds up until a report is
+//! sent, as the host reads the reports slower than the ticks. The wheel
+//! scrolls one step as soon as its key is pressed, then at a constant
+//! speed. Likewise, a button clicked between two reports is reported
+//! pressed, then released.
+
+/// Buttons of `MouseAction::Button`, and of the report.
+pub mod buttons {
+    pub const LEFT: u8 = 1 << 0;
+    pub const RIGHT: u8 = 1 << 1;
+    pub const MIDDLE: u8 = 1 << 2;
+    pub const BACK: u8 = 1 << 3;
+    pub const FORWARD: u8 = 1 << 4;
+}
+
+/// Motion of a pixel, in µpx.
+const PX: i32 = 1_000_000;
+
+/// Motion of a wheel step, in thousandths of a step.
+const STEP: i32 = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MouseAction {
+    /// Moves the pointer while held.
+    Up,
+    Down,
+    Left,
+    Right,
+    /// Scrolls while held.
+    WheelUp,
+    WheelDown,
+    WheelLeft,
+    WheelRight,
+    /// Holds the buttons, a bit set of `buttons`.
+    Button(u8),
+}
+
+/// How the pointer speeds up while its keys are held.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Curve {
+    /// Always the maximum speed.
+    Constant,
+    /// From the initial to the maximum speed, in `ramp` ms.
+    Linear,
+    /// Speeds up as `Linear`, slows down before turning back, and glides
+    /// to a stop once the keys are released.
+    Inertia,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MouseKeys {
+    pub curve: Curve,
+    /// Speed of the pointer when a movement key is pressed, in px/s.
+    pub initial_speed: u16,
+    pub max_speed: u16,
+    /// Time from the initial to the maximum speed, in ms.
+    pub ramp: u16,
+    /// With inertia, time from the maximum speed to a stop, in ms.
+    pub glide: u16,
+    /// Speed of the wheel, in steps per s.
+    pub wheel_speed: u16,
+}
+
+impl MouseKeys {
+    /// A linear ramp from 200 to 1200 px/s in 600 ms, the wheel
+    /// scrolling 12 steps per s.
+    pub const fn new() -> Self {
+        MouseKeys {
+            curve: Curve::Linear,
+            initial_speed: 200,
+            max_speed: 1200,
+            ramp: 600,
+            glide: 300,
+            wheel_speed: 12,
+        }
+    }
+
+    /// The velocity of an axis, in µpx/ms, at `elapsed` ms since the
+    /// pointer started moving, `dir` being the direction of the keys
+    /// and `v` the velocity at the previous tick.
+    fn velocity(&self, v: i32, dir: i32, elapsed: u32) -> i32 {
+        let initial = self.initial_speed as i32 * 1000;
+        let max = self.max_speed as i32 * 1000;
+        let ramp = self.ramp.max(1) as i32;
+        match self.curve {
+            Curve::Constant => dir * max,
+            Curve::Linear => {
+                let t = elapsed.min(ramp as u32) as i64;
+                dir * (initial + ((max - initial) as i64 * t / ramp as i64) as i32)
+            }
+            Curve::Inertia if dir == 0 => {
+                let friction = (max / self.glide.max(1) as i32).max(1);
+                v - v.signum() * friction.min(v.abs())
+            }
+            Curve::Inertia if v == 0 => dir * initial,
+            Curve::Inertia => {
+                let accel = ((max - initial) / ramp).max(1);
+                (v + dir * accel).clamp(-max, max)
+            }
+        }
+    }
+}
+
+impl Default for MouseKeys {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The mouse keys held at a tick.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Held {
+    pub buttons: u8,
+    /// Directions of the pointer and of the wheel, -1, 0 or 1.
+    pub x: i8,
+    pub y: i8,
+    pub wheel: i8,
+    pub pan: i8,
+}
+
+impl Held {
+    pub fn add(&mut self, action: MouseAction) {
+        let dir = |d: &mut i8, by: i8| *d = (*d + by).clamp(-1, 1);
+        match action {
+            MouseAction::Up => dir(&mut self.y, -1),
+            MouseAction::Down => dir(&mut self.y, 1),
+            MouseAction::Left => dir(&mut self.x, -1),
+            MouseAction::Right => dir(&mut self.x, 1),
+            MouseAction::WheelUp => dir(&mut self.wheel, 1),
+            MouseAction::WheelDown => dir(&mut self.wheel, -1),
+            MouseAction::WheelLeft => dir(&mut self.pan, -1),
+            MouseAction::WheelRight => dir(&mut self.pan, 1),
+            MouseAction::Button(b) => self.buttons |= b,
+        }
+    }
+
+    fn moving(&self) -> bool {
+        self.x != 0 || self.y != 0
+    }
+}
+
+/// A report of the mouse interface.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MouseReport {
+    pub buttons: u8,
+    pub x: i8,
+    pub y: i8,
+    pub wheel: i8,
+    /// Horizontal scrolling, AC Pan.
+    pub pan: i8,
+}
+
+impl MouseReport {
+    pub fn as_bytes(&self) -> [u8; 5] {
+        [
+            self.buttons,
+            self.x as u8,
+            self.y as u8,
+            self.wheel as u8,
+            self.pan as u8,
+        ]
+    }
+}
+
+pub struct Mouse {
+    keys: MouseKeys,
+    held: Held,
+    /// Time the pointer started moving.
+    since: u32,
+    /// Velocity of the pointer on x and y, in µpx/ms.
+    velocity: [i32; 2],
+    /// Motion not sent yet: x and y in µpx, then the wheel and pan in
+    /// thousandths of a step.
+    motion: [i32; 4],
+    /// Buttons pressed since the last report sent.
+    pressed: u8,
+    /// Buttons of the last report sent.
+    sent_buttons: u8,
+}
+
+impl Mouse {
+    pub fn new(keys: MouseKeys) -> Self {
+        Mouse {
+            keys,
+            held: Held::default(),
+            since: 0,
+            velocity: [0; 2],
+            motion: [0; 4],
+            pressed: 0,
+            sent_buttons: 0,
+        }
+    }
+
+    /// Moves and scrolls for one ms at `time`, with the keys `held`.
+    pub fn tick(&mut self, time: u32, held: Held) {
+        if held.moving() && !self.held.moving() {
+            self.since = time;
+        }
+        let elapsed = time.wrapping_sub(self.since);
+        for (axis, &dir) in [held.x, held.y].iter().enumerate() {
+            let v = self.keys.velocity(self.velocity[axis], dir as i32, elapsed);
+            self.velocity[axis] = v;
+            self.motion[axis] = (self.motion[axis] + v).clamp(-127 * PX, 127 * PX);
+        }
+        let wheels = [(held.wheel, self.held.wheel), (held.pan, self.held.pan)];
+        for (i, &(dir, previous)) in wheels.iter().enumerate() {
+            let motion = &mut self.motion[2 + i];
+            let dir = dir as i32;
+            if dir == 0 {
+                // Partial steps are dropped
+                *motion -= *motion % STEP;
+            } else if dir != previous as i32 {
+                *motion += dir * STEP;
+            } else {
+                *motion += dir * self.keys.wheel_speed as i32;
+            }
+            *motion = (*motion).clamp(-127 * STEP, 127 * STEP);
+        }
+        self.pressed |= held.buttons & !self.held.buttons;
+        self.held = held;
+    }
+
+    /// The report of the motion not sent yet, none if the pointer didn't
+    /// move and the buttons didn't change.
+    pub fn report(&self) -> Option<MouseReport> {
+        let report = MouseReport {
+            buttons: self.held.buttons | self.pressed,
+            x: (self.motion[0] / PX) as i8,
+            y: (self.motion[1] / PX) as i8,
+            wheel: (self.motion[2] / STEP) as i8,
+            pan: (self.motion[3] / STEP) as i8,
+        };
+        let moved = report.x != 0 || report.y != 0 || report.wheel != 0 || report.pan != 0;
+        (moved || report.buttons != self.sent_buttons).then_some(report)
+    }
+
+    /// Tells that `report` was sent, so its motion is not sent again.
+    pub fn report_sent(&mut self, report: &MouseReport) {
+        self.sent_buttons = report.buttons;
+        self.pressed = 0;
+        self.motion[0] -= report.x as i32 * PX;
+        self.motion[1] -= report.y as i32 * PX;
+        self.motion[2] -= report.wheel as i32 * STEP;
+        self.motion[3] -= report.pan as i32 * STEP;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::action::{CustomAction, Layers};
+    use crate::scenario::Scenario;
+    use keyberon::action::Action::*;
+    use keyberon_macros::layout;
+
+    static LAYERS: Layers = layout! {
+        {[
+            {Custom(CustomAction::Mouse(MouseAction::Right))}
+            {Custom(CustomAction::Mouse(MouseAction::Down))}
+            {Custom(CustomAction::Mouse(MouseAction::WheelDown))}
+            {Custom(CustomAction::Mouse(MouseAction::Button(buttons::LEFT)))}
+        ]}
+    };
+
+    static CONSTANT: MouseKeys = MouseKeys {
+        curve: Curve::Constant,
+        ..MouseKeys::new()
+    };
+
+    fn keys(curve: Curve) -> MouseKeys {
+        MouseKeys {
+            curve,
+            initial_speed: 100,
+            max_speed: 1100,
+            ramp: 100,
+            glide: 50,
+            wheel_speed: 10,
+        }
+    }
+
+    /// The velocities of an axis, in px/s, at each tick.
+    fn velocities(curve: Curve, dirs: &[i32]) -> Vec<i32> {
+        let keys = keys(curve);
+        let mut v = 0;
+        let mut since = 0;
+        let mut velocities = vec![];
+        for (time, &dir) in dirs.iter().enumerate() {
+            if dir == 0 {
+                since = time as u32 + 1;
+            }
+            v = keys.velocity(v, dir, time as u32 - since.min(time as u32));
+            velocities.push(v / 1000);
+        }
+        velocities
+    }
+
+    #[test]
+    fn test_curves() {
+        let held = [1; 101];
+        let constant = velocities(Curve::Constant, &held);
+        assert!(constant.iter().all(|&v| v == 1100));
+        let linear = velocities(Curve::Linear, &held);
+        assert_eq!((linear[0], linear[50], linear[100]), (100, 600, 1100));
+        assert_eq!(
+            velocities(Curve::Linear, &[1, 1, 0, -1]),
+            [100, 110, 0, -100]
+        );
+        let inertia = velocities(Curve::Inertia, &held);
+        assert_eq!((inertia[0], inertia[50], inertia[100]), (100, 600, 1100));
+        // Released: glides to a stop
+        let glide: Vec<_> = held.iter().chain(&[0; 60]).copied().collect();
+        let inertia = velocities(Curve::Inertia, &glide);
+        assert_eq!((inertia[101], inertia[125], inertia[150]), (1078, 550, 0));
+        // Turned back: slows down first
+        let back: Vec<_> = held.iter().chain(&[-1; 50]).copied().collect();
+        assert_eq!(velocities(Curve::Inertia, &back)[150], 600);
+    }
+
+    #[test]
+    fn test_motion() {
+        let mut mouse = Mouse::new(keys(Curve::Constant));
+        let right = Held {
+            x: 1,
+            ..Held::default()
+        };
+        mouse.tick(0, right);
+        assert_eq!(mouse.report().map(|r| r.x), Some(1));
+        for time in 1..10 {
+            mouse.tick(time, right);
+        }
+        let report = mouse.report().unwrap();
+        assert_eq!((report.x, report.y), (11, 0));
+        mouse.report_sent(&report);
+        assert_eq!(mouse.report(), None);
+        mouse.tick(10, Held::default());
+        assert_eq!(mouse.report(), None);
+        mouse.tick(11, right);
+        assert_eq!(mouse.report().map(|r| r.x), Some(1));
+        // Not sent: up to 127 px at once
+        for time in 12..200 {
+            mouse.tick(time, right);
+        }
+        assert_eq!(mouse.report().map(|r| r.x), Some(127));
+    }
+
+    #[test]
+    fn test_wheel() {
+        let mut mouse = Mouse::new(keys(Curve::Constant));
+        let down = Held {
+            wheel: -1,
+            ..Held::default()
+        };
+        mouse.tick(0, down);
+        let report = mouse.report().unwrap();
+        assert_eq!(report.wheel, -1);
+        mouse.report_sent(&report);
+        for time in 1..100 {
+            mouse.tick(time, down);
+            assert_eq!(mouse.report(), None);
+        }
+        mouse.tick(100, down);
+        assert_eq!(mouse.report().map(|r| r.wheel), Some(-1));
+    }
+
+    #[test]
+    fn test_mouse_keys() {
+        Scenario::new(LAYERS)
+            .mouse_keys(&CONSTANT)
+            .polling(8)
+            .press(0, 0)
+            .press(0, 1)
+            .tick(6)
+            .release(0, 1)
+            .release(0, 0)
+            .tick(6)
+            .expect_pointer(10, 8)
+            .tap(0, 3)
+            .tick(6)
+            .expect_mouse(MouseReport {
+                buttons: 1,
+                ..MouseReport::default()
+            })
+            .tap(0, 2)
+            .tick(10)
+            .expect_mouse(MouseReport {
+                wheel: -1,
+                ..MouseReport::default()
+            });
+    }
+}
@@ -0,0 +1,124 @@
+//! N-key rollover: a report with a bit per key, so that any number of
+//! keys are pressed at once.
+//!
+//! It is sent on an interface of its own, the boot keyboard interface
+//! staying as is for the BIOSes and such, that only know the 6 keys
+//! report. In NKRO mode, the keys go in the NKRO report and the boot
+//! report stays empty, and the other way round in 6KRO mode, or while
+//! the host selected the boot protocol.
+
+use keyberon::key_code::KeyCode;
+
+/// Number of keycodes of the bitmap, from `No` up to `ExSel` and a bit
+/// more.
+pub const KEYS: usize = 168;
+
+/// The modifiers, then a bit per keycode, the least significant bit of
+/// the first byte being `No`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NkroReport([u8; 1 + KEYS / 8]);
+
+impl Default for NkroReport {
+    fn default() -> Self {
+        NkroReport([0; 1 + KEYS / 8])
+    }
+}
+
+impl NkroReport {
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Presses `kc`, ignored if out of the bitmap.
+    pub fn pressed(&mut self, kc: KeyCode) {
+        let n = kc as usize;
+        if kc.is_modifier() {
+            self.0[0] |= kc.as_modifier_bit();
+        } else if kc != KeyCode::No && n < KEYS {
+            self.0[1 + n / 8] |= 1 << (n % 8);
+        }
+    }
+}
+
+impl core::iter::FromIterator<KeyCode> for NkroReport {
+    fn from_iter<T: IntoIterator<Item = KeyCode>>(iter: T) -> Self {
+        let mut report = Self::default();
+        for kc in iter {
+            report.pressed(kc);
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::action::{CustomAction, Layers};
+    use crate::scenario::Scenario;
+    use crate::settings::{features, Settings};
+    use keyberon::action::Action::*;
+    use keyberon::key_code::KbHidReport;
+    use keyberon::key_code::KeyCode::*;
+    use keyberon_macros::layout;
+
+    static LAYERS: Layers = layout! {
+        { [A B C D E F G {Custom(CustomAction::Nkro)}] [LShift n n n n n n n] }
+    };
+
+    #[test]
+    fn test_report() {
+        let report: NkroReport = [LShift, A, ExSel, MediaMute].iter().copied().collect();
+        let bytes = report.as_bytes();
+        assert_eq!(bytes.len(), 22);
+        assert_eq!(bytes[0], 0b10);
+        assert_eq!(bytes[1], 1 << A as u8);
+        assert_eq!(bytes[1 + ExSel as usize / 8], 1 << (ExSel as u8 % 8));
+        assert_eq!(bytes.iter().map(|b| b.count_ones()).sum::<u32>(), 3);
+    }
+
+    #[test]
+    fn test_nkro() {
+        let nkro = Settings {
+            features: features::NKRO,
+            ..Settings::default()
+        };
+        let mut scenario = Scenario::with_settings(LAYERS, &nkro).press(1, 0);
+        for j in 0..7 {
+            scenario = scenario.press(0, j);
+        }
+        scenario
+            .expect(&[LShift, A, B, C, D, E, F, G])
+            .expect_boot(KbHidReport::default())
+            .release(1, 0)
+            .tap(0, 7) // 6KRO
+            .expect(&[A, B, C, D, E, F, G])
+            .expect_boot([ErrorRollOver].iter().copied().collect());
+    }
+
+    #[test]
+    fn test_boot_protocol() {
+        let nkro = Settings {
+            features: features::NKRO,
+            ..Settings::default()
+        };
+        Scenario::with_settings(LAYERS, &nkro)
+            .press(0, 0)
+            .boot_protocol(true)
+            .expect_boot([A].iter().copied().collect())
+            .expect(&[A])
+            .boot_protocol(false)
+            .expect_boot(KbHidReport::default())
+            .expect(&[A]);
+        assert!(!Scenario::with_settings(LAYERS, &nkro)
+            .boot_protocol(true)
+            .keyboard()
+            .nkro());
+    }
+
+    #[test]
+    fn test_features() {
+        let scenario = Scenario::new(LAYERS).tap(0, 7);
+        assert_eq!(scenario.keyboard().features(), features::NKRO);
+        assert_eq!(scenario.tap(0, 7).keyboard().features(), 0);
+    }
+}
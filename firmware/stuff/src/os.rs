@@ -0,0 +1,103 @@
+//! Host OS profiles.
+//!
+//! The keymap is written once, with the `Cut`, `Copy`, `Paste`, `Undo`
+//! and `Again` (redo) keycodes for the shortcuts that differ between
+//! OSes. The profile translates them into the shortcuts of the host, and
+//! on macOS swaps Ctrl and Gui so that the Ctrl of the keymap is Cmd.
+//! This is done on the keycodes of the report only, the layers are the
+//! same for all profiles.
+
+use keyberon::key_code::KeyCode::{self, *};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Os {
+    #[default]
+    Linux = 0,
+    MacOs = 1,
+    Windows = 2,
+}
+
+impl Os {
+    /// The profile stored as `n` in the settings, if any.
+    pub fn from_u8(n: u8) -> Option<Self> {
+        match n {
+            0 => Some(Os::Linux),
+            1 => Some(Os::MacOs),
+            2 => Some(Os::Windows),
+            _ => None,
+        }
+    }
+
+    /// The keycodes sent for `kc`.
+    pub fn translate(self, kc: &KeyCode) -> &[KeyCode] {
+        match (self, *kc) {
+            // Shift+Delete and such also work in terminals
+            (Os::Linux, Cut) => &[LShift, Delete],
+            (Os::Linux, Copy) => &[LCtrl, Insert],
+            (Os::Linux, Paste) => &[LShift, Insert],
+            (Os::Linux, Undo) => &[LCtrl, Z],
+            (Os::Linux, Again) => &[LCtrl, LShift, Z],
+            (Os::MacOs, Cut) => &[LGui, X],
+            (Os::MacOs, Copy) => &[LGui, C],
+            (Os::MacOs, Paste) => &[LGui, V],
+            (Os::MacOs, Undo) => &[LGui, Z],
+            (Os::MacOs, Again) => &[LGui, LShift, Z],
+            (Os::MacOs, LCtrl) => &[LGui],
+            (Os::MacOs, RCtrl) => &[RGui],
+            (Os::MacOs, LGui) => &[LCtrl],
+            (Os::MacOs, RGui) => &[RCtrl],
+            (Os::Windows, Cut) => &[LCtrl, X],
+            (Os::Windows, Copy) => &[LCtrl, C],
+            (Os::Windows, Paste) => &[LCtrl, V],
+            (Os::Windows, Undo) => &[LCtrl, Z],
+            (Os::Windows, Again) => &[LCtrl, Y],
+            _ => core::slice::from_ref(kc),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::action::{CustomAction, Layers};
+    use crate::scenario::Scenario;
+    use crate::settings::Settings;
+    use keyberon::action::Action::*;
+    use keyberon_macros::layout;
+
+    static LAYERS: Layers = layout! {
+        { [LCtrl Copy A {Custom(CustomAction::Os(Os::MacOs))}] }
+    };
+
+    #[test]
+    fn test_translate() {
+        assert_eq!(Os::Linux.translate(&Paste), &[LShift, Insert]);
+        assert_eq!(Os::Windows.translate(&Again), &[LCtrl, Y]);
+        assert_eq!(Os::MacOs.translate(&LGui), &[LCtrl]);
+        assert_eq!(Os::Windows.translate(&LGui), &[LGui]);
+        assert_eq!(Os::MacOs.translate(&A), &[A]);
+        assert_eq!(Os::from_u8(Os::Windows as u8), Some(Os::Windows));
+        assert_eq!(Os::from_u8(3), None);
+    }
+
+    #[test]
+    fn test_profile() {
+        let windows = Settings {
+            os: Os::Windows as u8,
+            ..Settings::default()
+        };
+        Scenario::with_settings(LAYERS, &windows)
+            .press(0, 1)
+            .expect(&[LCtrl, C])
+            .release(0, 1)
+            .tap(0, 3) // macOS
+            .press(0, 1)
+            .expect(&[LGui, C])
+            .press(0, 0)
+            .press(0, 2)
+            .expect(&[LGui, C, A])
+            .release(0, 1)
+            .expect(&[LGui, A]);
+    }
+}
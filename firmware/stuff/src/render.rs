@@ -0,0 +1,568 @@
+//! Rendering of the keymap as printable cheat sheets.
+
+use crate::action::{Action, CustomAction, Layers};
+use crate::dimensions::Geometry;
+use crate::media::MediaKey;
+use crate::mouse::{buttons, MouseAction};
+use crate::os::Os;
+use crate::unicode::InputMethod;
+use arrayvec::ArrayString;
+use core::fmt::{self, Write};
+use keyberon::key_code::KeyCode;
+
+/// Size of a key in the SVG, in pixels per quarter of a key.
+const QUARTER: u16 = 14;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Key,
+    Layer,
+    Trans,
+    NoOp,
+}
+
+impl Kind {
+    fn class(self) -> &'static str {
+        match self {
+            Kind::Key => "key",
+            Kind::Layer => "key layer",
+            Kind::Trans => "key trans",
+            Kind::NoOp => "key noop",
+        }
+    }
+}
+
+/// What is printed on a key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Legend {
+    pub kind: Kind,
+    /// The main legend, what the key does when tapped.
+    pub tap: ArrayString<12>,
+    /// What the key does when held, if it differs.
+    pub hold: ArrayString<12>,
+}
+
+impl Legend {
+    fn new(kind: Kind, tap: &str) -> Self {
+        let mut legend = Legend {
+            kind,
+            tap: ArrayString::new(),
+            hold: ArrayString::new(),
+        };
+        push(&mut legend.tap, tap);
+        legend
+    }
+}
+
+fn push<const N: usize>(s: &mut ArrayString<N>, text: &str) {
+    for c in text.chars() {
+        if s.try_push(c).is_err() {
+            break;
+        }
+    }
+}
+
+pub fn legend(action: &Action) -> Legend {
+    use KeyCode::{LShift, RShift};
+    match action {
+        Action::NoOp => Legend::new(Kind::NoOp, ""),
+        Action::Trans => Legend::new(Kind::Trans, "_"),
+        Action::KeyCode(kc) => Legend::new(Kind::Key, name(*kc)),
+        Action::MultipleKeyCodes(&[LShift, kc]) | Action::MultipleKeyCodes(&[RShift, kc])
+            if shifted(kc).is_some() =>
+        {
+            Legend::new(Kind::Key, shifted(kc).unwrap())
+        }
+        Action::MultipleKeyCodes(kcs) => {
+            let mut legend = Legend::new(Kind::Key, "");
+            for (i, &kc) in kcs.iter().enumerate() {
+                if i > 0 {
+                    push(&mut legend.tap, "+");
+                }
+                push(&mut legend.tap, name(kc));
+            }
+            legend
+        }
+        Action::MultipleActions(actions) => {
+            let mut legend = Legend::new(Kind::Key, "");
+            for (i, action) in actions.iter().enumerate() {
+                if i > 0 {
+                    push(&mut legend.tap, "+");
+                }
+                push(&mut legend.tap, &self::legend(action).tap);
+            }
+            legend
+        }
+        Action::Layer(l) => {
+            let mut legend = Legend::new(Kind::Layer, "");
+            let _ = write!(legend.tap, "L{}", l);
+            legend
+        }
+        Action::DefaultLayer(l) => {
+            let mut legend = Legend::new(Kind::Layer, "");
+            let _ = write!(legend.tap, "Base {}", l);
+            legend
+        }
+        Action::HoldTap { hold, tap, .. } => {
+            let mut legend = self::legend(tap);
+            legend.hold = self::legend(hold).tap;
+            legend
+        }
+        Action::Custom(CustomAction::HoldTap(ht)) => {
+            let mut legend = self::legend(&ht.tap);
+            legend.hold = self::legend(&ht.hold).tap;
+            legend
+        }
+        // Starred, as on the one-shot keys of some keycaps
+        Action::Custom(CustomAction::OneShot(one_shot)) => {
+            let mut legend = self::legend(&one_shot.action);
+            push(&mut legend.tap, "*");
+            legend
+        }
+        Action::Custom(CustomAction::TapDance(dance)) => {
+            let mut legend = self::legend(dance.action(1, false));
+            for action in dance.taps.iter().skip(1) {
+                push(&mut legend.tap, " ");
+                push(&mut legend.tap, &self::legend(action).tap);
+            }
+            if let Some(hold) = dance.holds.first() {
+                legend.hold = self::legend(hold).tap;
+            }
+            legend
+        }
+        Action::Custom(CustomAction::Leader) => Legend::new(Kind::Key, "Lead"),
+        Action::Custom(CustomAction::CapsWord(_)) => Legend::new(Kind::Key, "CapsWd"),
+        Action::Custom(CustomAction::AutoShift) => Legend::new(Kind::Key, "AutoSh"),
+        Action::Custom(CustomAction::Nkro) => Legend::new(Kind::Key, "NKRO"),
+        Action::Custom(CustomAction::Bootloader) => Legend::new(Kind::Key, "Boot"),
+        Action::Custom(CustomAction::Reset) => Legend::new(Kind::Key, "Reset"),
+        Action::Custom(CustomAction::ToggleLayer(l)) => {
+            let mut legend = Legend::new(Kind::Layer, "");
+            let _ = write!(legend.tap, "Tg L{}", l);
+            legend
+        }
+        Action::Custom(CustomAction::LayerLock) => Legend::new(Kind::Layer, "Lock"),
+        Action::Custom(CustomAction::RecordMacro(slot)) => {
+            let mut legend = Legend::new(Kind::Key, "");
+            let _ = write!(legend.tap, "Rec {}", slot);
+            legend
+        }
+        Action::Custom(CustomAction::StopMacro) => Legend::new(Kind::Key, "Stop"),
+        Action::Custom(CustomAction::SequenceMacro(_)) => Legend::new(Kind::Key, "Macro"),
+        Action::Custom(CustomAction::Unicode(c)) => {
+            let mut legend = Legend::new(Kind::Key, "");
+            let _ = legend.tap.try_push(*c);
+            legend
+        }
+        Action::Custom(CustomAction::UnicodeInput(method)) => Legend::new(
+            Kind::Key,
+            match method {
+                InputMethod::Linux => "U Lnx",
+                InputMethod::MacOs => "U Mac",
+                InputMethod::WinCompose => "U WinC",
+            },
+        ),
+        Action::Custom(CustomAction::PlayMacro(slot)) => {
+            let mut legend = Legend::new(Kind::Key, "");
+            let _ = write!(legend.tap, "Play {}", slot);
+            legend
+        }
+        Action::Custom(CustomAction::Os(os)) => Legend::new(
+            Kind::Key,
+            match os {
+                Os::Linux => "Linux",
+                Os::MacOs => "macOS",
+                Os::Windows => "Win",
+            },
+        ),
+        Action::Custom(CustomAction::Mouse(action)) => Legend::new(
+            Kind::Key,
+            match action {
+                MouseAction::Up => "Ms ↑",
+                MouseAction::Down => "Ms ↓",
+                MouseAction::Left => "Ms ←",
+                MouseAction::Right => "Ms →",
+                MouseAction::WheelUp => "Wh ↑",
+                MouseAction::WheelDown => "Wh ↓",
+                MouseAction::WheelLeft => "Wh ←",
+                MouseAction::WheelRight => "Wh →",
+                MouseAction::Button(buttons::LEFT) => "Btn L",
+                MouseAction::Button(buttons::RIGHT) => "Btn R",
+                MouseAction::Button(buttons::MIDDLE) => "Btn M",
+                MouseAction::Button(buttons::BACK) => "Back",
+                MouseAction::Button(buttons::FORWARD) => "Fwd",
+                MouseAction::Button(_) => "Btns",
+            },
+        ),
+        Action::Custom(CustomAction::Media(key)) => Legend::new(
+            Kind::Key,
+            match key {
+                MediaKey::Mute => "Mute",
+                MediaKey::VolumeUp => "Vol+",
+                MediaKey::VolumeDown => "Vol-",
+                MediaKey::PlayPause => "Play",
+                MediaKey::Stop => "■",
+                MediaKey::NextTrack => "Next",
+                MediaKey::PrevTrack => "Prev",
+                MediaKey::BrightnessUp => "Bri+",
+                MediaKey::BrightnessDown => "Bri-",
+                MediaKey::Sleep => "Sleep",
+                MediaKey::Power => "Power",
+                MediaKey::Wake => "Wake",
+            },
+        ),
+    }
+}
+
+fn name(kc: KeyCode) -> &'static str {
+    const LETTERS: [&str; 26] = [
+        "A", "B", "C", "D", "E", "F", "G", "H", "I", "J", "K", "L", "M", "N", "O", "P", "Q", "R",
+        "S", "T", "U", "V", "W", "X", "Y", "Z",
+    ];
+    const DIGITS: [&str; 10] = ["1", "2", "3", "4", "5", "6", "7", "8", "9", "0"];
+    use KeyCode::*;
+    match kc {
+        _ if A <= kc && kc <= Z => LETTERS[(kc as u8 - A as u8) as usize],
+        _ if Kb1 <= kc && kc <= Kb0 => DIGITS[(kc as u8 - Kb1 as u8) as usize],
+        Enter => "Enter",
+        Escape => "Esc",
+        BSpace => "Bksp",
+        Tab => "Tab",
+        Space => "Space",
+        Minus => "-",
+        Equal => "=",
+        LBracket => "[",
+        RBracket => "]",
+        Bslash => "\\",
+        SColon => ";",
+        Quote => "'",
+        Grave => "`",
+        Comma => ",",
+        Dot => ".",
+        Slash => "/",
+        Delete => "Del",
+        PgDown => "PgDn",
+        LShift | RShift => "Shift",
+        LCtrl | RCtrl => "Ctrl",
+        LAlt => "Alt",
+        RAlt => "AltGr",
+        LGui | RGui => "Gui",
+        F1 => "F1",
+        F2 => "F2",
+        F3 => "F3",
+        F4 => "F4",
+        F5 => "F5",
+        F6 => "F6",
+        F7 => "F7",
+        F8 => "F8",
+        F9 => "F9",
+        F10 => "F10",
+        F11 => "F11",
+        F12 => "F12",
+        PgUp => "PgUp",
+        Home => "Home",
+        End => "End",
+        Insert => "Ins",
+        Left => "Left",
+        Right => "Right",
+        Up => "Up",
+        Down => "Down",
+        CapsLock => "Caps",
+        PScreen => "PrtSc",
+        Cut => "Cut",
+        Copy => "Copy",
+        Paste => "Paste",
+        Undo => "Undo",
+        Again => "Redo",
+        _ => "?",
+    }
+}
+
+/// The symbol typed by `kc` with shift, on a US layout.
+fn shifted(kc: KeyCode) -> Option<&'static str> {
+    use KeyCode::*;
+    Some(match kc {
+        _ if A <= kc && kc <= Z => name(kc),
+        Kb1 => "!",
+        Kb2 => "@",
+        Kb3 => "#",
+        Kb4 => "$",
+        Kb5 => "%",
+        Kb6 => "^",
+        Kb7 => "&",
+        Kb8 => "*",
+        Kb9 => "(",
+        Kb0 => ")",
+        Minus => "_",
+        Equal => "+",
+        LBracket => "{",
+        RBracket => "}",
+        Bslash => "|",
+        SColon => ":",
+        Quote => "\"",
+        Grave => "~",
+        Comma => "<",
+        Dot => ">",
+        Slash => "?",
+        _ => return None,
+    })
+}
+
+fn at(layers: Layers, layer: usize, i: usize, j: usize) -> &'static Action {
+    layers[layer]
+        .get(i)
+        .and_then(|r| r.get(j))
+        .unwrap_or(&Action::NoOp)
+}
+
+/// Renders `layer` as text, transparent keys shown as `_` and no-op
+/// keys left empty.
+pub fn ascii<const W: usize>(
+    layers: Layers,
+    layer: usize,
+    geometry: &Geometry<W>,
+    out: &mut impl Write,
+) -> fmt::Result {
+    writeln!(out, "Layer {}", layer)?;
+    // As wide as the widest legend, and at least 6 characters
+    let width = (0..geometry.keys.len())
+        .flat_map(|i| (0..W).map(move |j| (i, j)))
+        .filter(|&(i, j)| geometry.position(i, j).is_some())
+        .map(|(i, j)| cell(layers, layer, i, j).chars().count())
+        .fold(6, usize::max);
+    for i in 0..geometry.keys.len() {
+        let mut line = ArrayString::<512>::new();
+        for j in 0..W {
+            let previous = j > 0 && geometry.position(i, j - 1).is_some();
+            if j == geometry.split {
+                push(&mut line, if previous { "|  " } else { "   " });
+            }
+            let bar = if previous && j != geometry.split {
+                "|"
+            } else {
+                " "
+            };
+            match geometry.position(i, j) {
+                Some(_) => write!(line, "|{:^w$}", cell(layers, layer, i, j), w = width)?,
+                None => write!(line, "{}{:w$}", bar, "", w = width)?,
+            }
+        }
+        let last = geometry.position(i, W - 1).is_some();
+        push(&mut line, if last { "|" } else { "" });
+        writeln!(out, "{}", line.trim_end())?;
+    }
+    Ok(())
+}
+
+/// The legends of the key at `(i, j)` of `layer`, as `tap/hold`.
+fn cell(layers: Layers, layer: usize, i: usize, j: usize) -> ArrayString<25> {
+    let legend = legend(at(layers, layer, i, j));
+    let mut cell = ArrayString::new();
+    push(&mut cell, &legend.tap);
+    if !legend.hold.is_empty() {
+        push(&mut cell, "/");
+        push(&mut cell, &legend.hold);
+    }
+    cell
+}
+
+/// Escapes `text` for XML.
+struct Xml<'a>(&'a str);
+
+impl fmt::Display for Xml<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for c in self.0.chars() {
+            match c {
+                '<' => f.write_str("&lt;")?,
+                '>' => f.write_str("&gt;")?,
+                '&' => f.write_str("&amp;")?,
+                '"' => f.write_str("&quot;")?,
+                c => f.write_char(c)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+const STYLE: &str = "\
+.key{fill:#fff;stroke:#333;stroke-width:1.5}\
+.layer{fill:#dde8ff}\
+.trans{fill:#f4f4f4;stroke-dasharray:4 3}\
+.noop{fill:#ccc}\
+text{font-family:sans-serif;font-size:14px;text-anchor:middle}\
+.hold{font-size:10px;fill:#555}";
+
+/// Renders `layer` as a SVG image.
+pub fn svg<const W: usize>(
+    layers: Layers,
+    layer: usize,
+    geometry: &Geometry<W>,
+    out: &mut impl Write,
+) -> fmt::Result {
+    let (width, height) = geometry.size();
+    let (width, height) = (width * QUARTER, height * QUARTER);
+    let size = 4 * QUARTER - 4;
+    writeln!(
+        out,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{0}" height="{1}" viewBox="0 0 {0} {1}">"#,
+        width, height
+    )?;
+    writeln!(out, "<style>{}</style>", STYLE)?;
+    for i in 0..geometry.keys.len() {
+        for j in 0..W {
+            let (x, y) = match geometry.position(i, j) {
+                Some(p) => p,
+                None => continue,
+            };
+            let legend = legend(at(layers, layer, i, j));
+            writeln!(
+                out,
+                r#"<g transform="translate({},{})"><rect class="{}" x="2" y="2" width="{}" height="{}" rx="5"/>"#,
+                x * QUARTER,
+                y * QUARTER,
+                legend.kind.class(),
+                size,
+                size
+            )?;
+            let text_y = if legend.hold.is_empty() { 33 } else { 27 };
+            if legend.kind == Kind::Trans {
+                writeln!(
+                    out,
+                    r#"<text x="{}" y="{}">&#9661;</text>"#,
+                    size / 2 + 2,
+                    text_y
+                )?;
+            } else if !legend.tap.is_empty() {
+                writeln!(
+                    out,
+                    r#"<text x="{}" y="{}">{}</text>"#,
+                    size / 2 + 2,
+                    text_y,
+                    Xml(&legend.tap)
+                )?;
+            }
+            if !legend.hold.is_empty() {
+                writeln!(
+                    out,
+                    r#"<text class="hold" x="{}" y="44">{}</text>"#,
+                    size / 2 + 2,
+                    Xml(&legend.hold)
+                )?;
+            }
+            writeln!(out, "</g>")?;
+        }
+    }
+    writeln!(out, "</svg>")
+}
+
+/// Renders all the layers as a printable HTML page, one layer per page.
+pub fn html<const W: usize>(
+    layers: Layers,
+    geometry: &Geometry<W>,
+    out: &mut impl Write,
+) -> fmt::Result {
+    writeln!(out, "<!DOCTYPE html>")?;
+    writeln!(
+        out,
+        r#"<html><head><meta charset="utf-8"><title>KeySeeBee keymap</title>"#
+    )?;
+    writeln!(
+        out,
+        "<style>body{{font-family:sans-serif}}section{{page-break-after:always}}</style>"
+    )?;
+    writeln!(out, "</head><body>")?;
+    for layer in 0..layers.len() {
+        writeln!(out, "<section><h2>Layer {}</h2>", layer)?;
+        svg(layers, layer, geometry, out)?;
+        writeln!(out, "</section>")?;
+    }
+    writeln!(out, "</body></html>")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::action::HoldTap;
+    use keyberon::action::{k, m, HoldTapConfig};
+    use keyberon::key_code::KeyCode::*;
+    use keyberon_macros::layout;
+
+    static LAYERS: Layers = layout! {
+        { [A B C] [(1) '"' n] }
+        { [t n !] [t [LCtrl C] n] }
+    };
+    const GEOMETRY: Geometry<3> = Geometry {
+        keys: &[[true, true, true], [true, true, false]],
+        stagger: [0, 0, 0],
+        split: 2,
+    };
+
+    #[test]
+    fn test_legend() {
+        assert_eq!(legend(&k(Escape)).tap.as_str(), "Esc");
+        assert_eq!(legend(&m(&[LShift, Kb1])).tap.as_str(), "!");
+        assert_eq!(legend(&m(&[LShift, Q])).tap.as_str(), "Q");
+        assert_eq!(legend(&m(&[LCtrl, LShift, Z])).tap.as_str(), "Ctrl+Shift+Z");
+        assert_eq!(legend(&Action::Trans).kind, Kind::Trans);
+        assert_eq!(legend(&Action::NoOp).kind, Kind::NoOp);
+        const HT: Action = Action::HoldTap {
+            timeout: 200,
+            hold: &k(LShift),
+            tap: &k(F),
+            config: HoldTapConfig::Default,
+            tap_hold_interval: 0,
+        };
+        let legend = legend(&HT);
+        assert_eq!((legend.tap.as_str(), legend.hold.as_str()), ("F", "Shift"));
+        const HRM: HoldTap = HoldTap::home_row_mod(LCtrl, D);
+        let legend = self::legend(&Action::Custom(CustomAction::HoldTap(&HRM)));
+        assert_eq!((legend.tap.as_str(), legend.hold.as_str()), ("D", "Ctrl"));
+    }
+
+    #[test]
+    fn test_ascii() {
+        let mut out = String::new();
+        ascii(LAYERS, 0, &GEOMETRY, &mut out).unwrap();
+        ascii(LAYERS, 1, &GEOMETRY, &mut out).unwrap();
+        assert_eq!(
+            out,
+            "\
+Layer 0
+|  A   |  B   |  |  C   |
+|  L1  |  \"   |
+Layer 1
+|  _   |      |  |  !   |
+|  _   |Ctrl+C|
+"
+        );
+        // Widened to fit the legends
+        static WIDE: Layers = layout! {
+            { [A [LCtrl LShift Z] C] [(1) D n] }
+        };
+        let mut out = String::new();
+        ascii(WIDE, 0, &GEOMETRY, &mut out).unwrap();
+        assert_eq!(
+            out,
+            "\
+Layer 0
+|     A      |Ctrl+Shift+Z|  |     C      |
+|     L1     |     D      |
+"
+        );
+    }
+
+    #[test]
+    fn test_svg() {
+        let mut out = String::new();
+        svg(LAYERS, 0, &GEOMETRY, &mut out).unwrap();
+        assert_eq!(out.matches("<rect").count(), 5);
+        assert!(out.contains(">&quot;</text>"));
+        assert!(out.contains(r#"class="key layer""#));
+
+        let mut out = String::new();
+        html(LAYERS, &GEOMETRY, &mut out).unwrap();
+        assert_eq!(out.matches("<svg").count(), 2);
+        assert_eq!(out.matches(r#"class="key trans""#).count(), 2);
+        assert_eq!(out.matches(r#"class="key noop""#).count(), 1);
+    }
+}
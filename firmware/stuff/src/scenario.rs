@@ -0,0 +1,292 @@
+//! Scenario tests of a keymap.
+//!
+//! A scenario drives a `Keyboard` with key events and time, checking
+//! the reports along the way:
+//!
+//! ```ignore
+//! Scenario::new(LAYERS)
+//!     .press(0, 9)
+//!     .tick(50)
+//!     .release(0, 9)
+//!     .expect(&[])
+//!     .golden("tap_o");
+//! ```
+//!
+//! As the firmware does, each event is followed by a tick. The reports
+//! sent are recorded with the events in a transcript, the keyboard ones
+//! as their bytes in hex, that `golden`
+//! compares to `golden/<name>.txt`. Run the tests with `UPDATE_GOLDEN=1`
+//! to write the golden files instead.
+
+use crate::action::Layers;
+use crate::auto_shift::AutoShift;
+use crate::combo::Combo;
+use crate::conditional::ConditionalLayer;
+use crate::key_override::KeyOverride;
+use crate::keyboard::{Keyboard, Reboot};
+use crate::leader::Leader;
+use crate::media::MediaReport;
+use crate::mouse::{MouseKeys, MouseReport};
+use crate::nkro::NkroReport;
+use crate::settings::Settings;
+use keyberon::key_code::{KbHidReport, KeyCode};
+use keyberon::layout::Event;
+use std::fmt::Write;
+use std::path::PathBuf;
+
+pub struct Scenario {
+    keyboard: Keyboard,
+    time: u32,
+    /// Time between two reads of the report by the host, in ms.
+    polling: u32,
+    last: KbHidReport,
+    last_nkro: NkroReport,
+    /// Sum of the motions of the mouse reports sent.
+    pointer: (i32, i32),
+    /// Last mouse report sent.
+    mouse: MouseReport,
+    /// Last media report sent.
+    media: Option<MediaReport>,
+    transcript: String,
+}
+
+impl Scenario {
+    pub fn new(layers: Layers) -> Self {
+        Self::with_settings(layers, &Settings::default())
+    }
+
+    pub fn with_settings(layers: Layers, settings: &Settings) -> Self {
+        Scenario {
+            keyboard: Keyboard::new(layers, settings),
+            time: 0,
+            polling: 1,
+            last: KbHidReport::default(),
+            last_nkro: NkroReport::default(),
+            pointer: (0, 0),
+            mouse: MouseReport::default(),
+            media: None,
+            transcript: String::new(),
+        }
+    }
+
+    pub fn combos(mut self, combos: &'static [Combo]) -> Self {
+        self.keyboard = self.keyboard.with_combos(combos);
+        self
+    }
+
+    pub fn conditional_layers(mut self, conditionals: &'static [ConditionalLayer]) -> Self {
+        self.keyboard = self.keyboard.with_conditional_layers(conditionals);
+        self
+    }
+
+    pub fn base_layers(mut self, base_layers: &'static [usize]) -> Self {
+        self.keyboard = self.keyboard.with_base_layers(base_layers);
+        self
+    }
+
+    pub fn key_overrides(mut self, key_overrides: &'static [KeyOverride]) -> Self {
+        self.keyboard = self.keyboard.with_key_overrides(key_overrides);
+        self
+    }
+
+    pub fn leader(mut self, leader: &'static Leader) -> Self {
+        self.keyboard = self.keyboard.with_leader(leader);
+        self
+    }
+
+    pub fn auto_shift(mut self, auto_shift: &'static AutoShift) -> Self {
+        self.keyboard = self.keyboard.with_auto_shift(auto_shift);
+        self
+    }
+
+    pub fn mouse_keys(mut self, mouse_keys: &'static MouseKeys) -> Self {
+        self.keyboard = self.keyboard.with_mouse_keys(mouse_keys);
+        self
+    }
+
+    /// Lets the host read the report every `ms` milliseconds, instead of
+    /// every ms.
+    pub fn polling(mut self, ms: u32) -> Self {
+        self.polling = ms;
+        self
+    }
+
+    /// Lets the host select the boot protocol, or the report one.
+    pub fn boot_protocol(mut self, boot: bool) -> Self {
+        self.keyboard.set_boot_protocol(boot);
+        self.tick(1)
+    }
+
+    pub fn press(self, i: u8, j: u8) -> Self {
+        self.event(Event::Press(i, j))
+    }
+
+    pub fn release(self, i: u8, j: u8) -> Self {
+        self.event(Event::Release(i, j))
+    }
+
+    /// Presses and releases a key.
+    pub fn tap(self, i: u8, j: u8) -> Self {
+        self.press(i, j).release(i, j)
+    }
+
+    pub fn event(mut self, event: Event) -> Self {
+        let _ = writeln!(self.transcript, "{:>6} ms: {:?}", self.time, event);
+        self.keyboard.event(event);
+        self.tick(1)
+    }
+
+    /// Lets `ms` milliseconds pass.
+    pub fn tick(mut self, ms: u32) -> Self {
+        for _ in 0..ms {
+            self.keyboard.tick();
+            self.time += 1;
+            if self.time.is_multiple_of(self.polling) {
+                self.keyboard.report_sent();
+                if let Some(report) = self.keyboard.mouse_report() {
+                    self.keyboard.mouse_report_sent(&report);
+                    self.pointer.0 += report.x as i32;
+                    self.pointer.1 += report.y as i32;
+                    self.mouse = report;
+                    let _ = writeln!(self.transcript, "{:>6} ms: {:?}", self.time, report);
+                }
+                if let Some(report) = self.keyboard.media_report() {
+                    self.keyboard.media_report_sent(&report);
+                    self.media = Some(report);
+                    let _ = writeln!(self.transcript, "{:>6} ms: {:?}", self.time, report);
+                }
+            }
+            let report = self.keyboard.report();
+            if report != self.last {
+                let _ = writeln!(self.transcript, "{:>6} ms: {}", self.time, boot(&report));
+                self.last = report;
+            }
+            let report = self.keyboard.nkro_report();
+            if report != self.last_nkro {
+                let _ = writeln!(self.transcript, "{:>6} ms: {}", self.time, nkro(&report));
+                self.last_nkro = report;
+            }
+        }
+        self
+    }
+
+    /// Checks that the current report holds exactly `keycodes`, the NKRO
+    /// report in NKRO mode.
+    #[track_caller]
+    pub fn expect(self, keycodes: &[KeyCode]) -> Self {
+        if self.keyboard.nkro() {
+            let expected: NkroReport = keycodes.iter().copied().collect();
+            assert_eq!(
+                self.keyboard.nkro_report(),
+                expected,
+                "at {} ms, keycodes {:?}, expected {:?}",
+                self.time,
+                self.keyboard.keycodes(),
+                keycodes
+            );
+            return self;
+        }
+        self.expect_boot(keycodes.iter().copied().collect())
+    }
+
+    /// Checks the boot report, whatever the mode.
+    #[track_caller]
+    pub fn expect_boot(self, expected: KbHidReport) -> Self {
+        assert_eq!(
+            self.keyboard.report(),
+            expected,
+            "at {} ms, keycodes {:?}",
+            self.time,
+            self.keyboard.keycodes(),
+        );
+        self
+    }
+
+    /// Checks that the pointer moved by `(x, y)` in all, with the mouse
+    /// reports sent.
+    #[track_caller]
+    pub fn expect_pointer(self, x: i32, y: i32) -> Self {
+        assert_eq!(self.pointer, (x, y), "at {} ms", self.time);
+        self
+    }
+
+    /// Checks the last mouse report sent.
+    #[track_caller]
+    pub fn expect_mouse(self, report: MouseReport) -> Self {
+        assert_eq!(self.mouse, report, "at {} ms", self.time);
+        self
+    }
+
+    /// Checks the last media report sent.
+    #[track_caller]
+    pub fn expect_media(self, report: MediaReport) -> Self {
+        assert_eq!(self.media, Some(report), "at {} ms", self.time);
+        self
+    }
+
+    /// Checks the reboot asked, and the one to do now.
+    #[track_caller]
+    pub fn expect_reboot(self, pending: Option<Reboot>, now: Option<Reboot>) -> Self {
+        assert_eq!(
+            (self.keyboard.reboot_pending(), self.keyboard.reboot()),
+            (pending, now),
+            "at {} ms",
+            self.time
+        );
+        self
+    }
+
+    pub fn keyboard(&self) -> &Keyboard {
+        &self.keyboard
+    }
+
+    pub fn transcript(&self) -> &str {
+        &self.transcript
+    }
+
+    /// Number of times the boot report of `keycodes` was sent.
+    pub fn sent(&self, keycodes: &[KeyCode]) -> usize {
+        let report = boot(&keycodes.iter().copied().collect());
+        self.transcript.matches(&format!(": {}\n", report)).count()
+    }
+
+    /// Checks the transcript against `golden/<name>.txt`.
+    #[track_caller]
+    pub fn golden(self, name: &str) {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("golden")
+            .join(name)
+            .with_extension("txt");
+        if std::env::var_os("UPDATE_GOLDEN").is_some() {
+            std::fs::write(&path, &self.transcript).unwrap();
+            return;
+        }
+        let golden = std::fs::read_to_string(&path).unwrap_or_else(|_| {
+            panic!(
+                "{} is missing, run with UPDATE_GOLDEN=1 to create it",
+                path.display()
+            )
+        });
+        assert_eq!(
+            self.transcript,
+            golden,
+            "transcript differs from {}, run with UPDATE_GOLDEN=1 to update it",
+            path.display()
+        );
+    }
+}
+
+/// A boot report as recorded in the transcript.
+fn boot(report: &KbHidReport) -> String {
+    format!("boot {}", hex(report.as_bytes()))
+}
+
+/// An NKRO report as recorded in the transcript.
+fn nkro(report: &NkroReport) -> String {
+    format!("nkro {}", hex(report.as_bytes()))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    let bytes: Vec<_> = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    bytes.join(" ")
+}
@@ -0,0 +1,325 @@
+//! Sequence macros: keys tapped one after the other, or text typed.
+//!
+//! Each step changes the report once: a tap is a report with its keys,
+//! then one without, and a character of a text is tapped likewise, or
+//! makes the reports of the Unicode input method. The next change waits
+//! for the report to be sent, so that no key is lost when the host reads
+//! the reports slower than they change.
+
+use crate::unicode::{self, InputMethod, Reports};
+use arrayvec::ArrayVec;
+use keyberon::key_code::KeyCode;
+
+/// Maximum number of keycodes pressed by a sequence macro at once.
+pub const MAX_PRESSED: usize = 6;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    /// Presses the keycodes together, then releases them.
+    Tap(&'static [KeyCode]),
+    Press(KeyCode),
+    Release(KeyCode),
+    /// Types the ASCII characters of a text, on a US layout. The other
+    /// characters are skipped.
+    Text(&'static str),
+    /// Types a text with the Unicode input method.
+    Unicode(&'static str),
+    /// Waits, in ms.
+    Delay(u16),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SequenceMacro {
+    pub steps: &'static [Step],
+}
+
+/// A sequence macro being typed.
+pub struct Typing {
+    steps: &'static [Step],
+    /// Index of the current step.
+    step: usize,
+    /// Index in the text of the current step.
+    index: usize,
+    method: InputMethod,
+    /// Reports of the current Unicode character, and the index of the
+    /// next one.
+    reports: Reports,
+    next_report: usize,
+    /// Whether the keys of the current tap or character are pressed.
+    tapped: bool,
+    /// Time until which the current delay waits.
+    until: Option<u32>,
+    keycodes: ArrayVec<KeyCode, MAX_PRESSED>,
+}
+
+impl Typing {
+    pub fn new(sequence: &'static SequenceMacro, method: InputMethod) -> Self {
+        Self::with_steps(sequence.steps, method)
+    }
+
+    /// Types the character `c` with the Unicode input method.
+    pub fn unicode(c: char, method: InputMethod) -> Self {
+        Typing {
+            reports: unicode::reports(c, method),
+            ..Self::with_steps(&[], method)
+        }
+    }
+
+    fn with_steps(steps: &'static [Step], method: InputMethod) -> Self {
+        Typing {
+            steps,
+            step: 0,
+            index: 0,
+            method,
+            reports: Reports::new(),
+            next_report: 0,
+            tapped: false,
+            until: None,
+            keycodes: ArrayVec::new(),
+        }
+    }
+
+    /// The keycodes pressed.
+    pub fn keycodes(&self) -> &[KeyCode] {
+        &self.keycodes
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.step == self.steps.len() && self.next_report == self.reports.len()
+    }
+
+    /// Changes the keycodes for the next report, at `time`. Returns
+    /// whether the report may have changed, false while waiting.
+    pub fn advance(&mut self, time: u32) -> bool {
+        if let Some(report) = self.reports.get(self.next_report) {
+            self.keycodes.clear();
+            self.keycodes.extend(report.iter().copied());
+            self.next_report += 1;
+            return true;
+        }
+        while let Some(&step) = self.steps.get(self.step) {
+            match step {
+                Step::Tap(keycodes) => {
+                    self.tap(keycodes.iter().copied());
+                    return true;
+                }
+                Step::Press(kc) => {
+                    let _ = self.keycodes.try_push(kc);
+                    self.step += 1;
+                    return true;
+                }
+                Step::Release(kc) => {
+                    self.keycodes.retain(|k| *k != kc);
+                    self.step += 1;
+                    return true;
+                }
+                Step::Text(text) => match text.as_bytes().get(self.index) {
+                    Some(&c) => match ascii(c) {
+                        Some((shifted, kc)) => {
+                            let shift = shifted.then_some(KeyCode::LShift);
+                            self.tap(shift.into_iter().chain(Some(kc)));
+                            return true;
+                        }
+                        None => self.index += 1,
+                    },
+                    None => {
+                        self.index = 0;
+                        self.step += 1;
+                    }
+                },
+                Step::Unicode(text) => match text[self.index..].chars().next() {
+                    Some(c) => {
+                        self.index += c.len_utf8();
+                        self.reports = unicode::reports(c, self.method);
+                        self.next_report = 0;
+                        return self.advance(time);
+                    }
+                    None => {
+                        self.index = 0;
+                        self.step += 1;
+                    }
+                },
+                Step::Delay(ms) => match self.until {
+                    None => self.until = Some(time.wrapping_add(ms as u32)),
+                    Some(until) if (time.wrapping_sub(until) as i32) < 0 => return false,
+                    Some(_) => {
+                        self.until = None;
+                        self.step += 1;
+                    }
+                },
+            }
+        }
+        false
+    }
+
+    /// Presses `keycodes`, or releases them and goes to the next tap.
+    fn tap(&mut self, keycodes: impl Iterator<Item = KeyCode>) {
+        if self.tapped {
+            self.keycodes.clear();
+            self.tapped = false;
+            match self.steps[self.step] {
+                Step::Text(_) => self.index += 1,
+                _ => self.step += 1,
+            }
+        } else {
+            self.keycodes.extend(keycodes.take(MAX_PRESSED));
+            self.tapped = true;
+        }
+    }
+}
+
+/// Whether the ASCII character `c` is typed with shift, and its key, on
+/// a US layout.
+pub fn ascii(c: u8) -> Option<(bool, KeyCode)> {
+    use KeyCode::*;
+    const DIGITS: [KeyCode; 10] = [Kb0, Kb1, Kb2, Kb3, Kb4, Kb5, Kb6, Kb7, Kb8, Kb9];
+    const LETTERS: [KeyCode; 26] = [
+        A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z,
+    ];
+    Some(match c {
+        b'a'..=b'z' => (false, LETTERS[(c - b'a') as usize]),
+        b'A'..=b'Z' => (true, LETTERS[(c - b'A') as usize]),
+        b'0'..=b'9' => (false, DIGITS[(c - b'0') as usize]),
+        b'\n' => (false, Enter),
+        b'\t' => (false, Tab),
+        b' ' => (false, Space),
+        b'-' => (false, Minus),
+        b'=' => (false, Equal),
+        b'[' => (false, LBracket),
+        b']' => (false, RBracket),
+        b'\\' => (false, Bslash),
+        b';' => (false, SColon),
+        b'\'' => (false, Quote),
+        b'`' => (false, Grave),
+        b',' => (false, Comma),
+        b'.' => (false, Dot),
+        b'/' => (false, Slash),
+        b'!' => (true, Kb1),
+        b'@' => (true, Kb2),
+        b'#' => (true, Kb3),
+        b'$' => (true, Kb4),
+        b'%' => (true, Kb5),
+        b'^' => (true, Kb6),
+        b'&' => (true, Kb7),
+        b'*' => (true, Kb8),
+        b'(' => (true, Kb9),
+        b')' => (true, Kb0),
+        b'_' => (true, Minus),
+        b'+' => (true, Equal),
+        b'{' => (true, LBracket),
+        b'}' => (true, RBracket),
+        b'|' => (true, Bslash),
+        b':' => (true, SColon),
+        b'"' => (true, Quote),
+        b'~' => (true, Grave),
+        b'<' => (true, Comma),
+        b'>' => (true, Dot),
+        b'?' => (true, Slash),
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::action::{CustomAction, Layers};
+    use crate::scenario::Scenario;
+    use keyberon::action::Action::*;
+    use keyberon::key_code::KeyCode::*;
+    use keyberon_macros::layout;
+
+    static HELLO: SequenceMacro = SequenceMacro {
+        steps: &[
+            Step::Text("Hi!"),
+            Step::Delay(10),
+            Step::Tap(&[LCtrl, Enter]),
+        ],
+    };
+
+    static LAYERS: Layers = layout! {
+        { [{Custom(CustomAction::SequenceMacro(&HELLO))} A] }
+    };
+
+    #[test]
+    fn test_ascii() {
+        assert_eq!(ascii(b'h'), Some((false, H)));
+        assert_eq!(ascii(b'H'), Some((true, H)));
+        assert_eq!(ascii(b'0'), Some((false, Kb0)));
+        assert_eq!(ascii(b'?'), Some((true, Slash)));
+        assert_eq!(ascii(0xc3), None);
+    }
+
+    #[test]
+    fn test_sequence_macro() {
+        Scenario::new(LAYERS)
+            .press(0, 0)
+            .expect(&[LShift, H])
+            .release(0, 0)
+            .expect(&[])
+            .tick(1)
+            .expect(&[I])
+            .tick(2)
+            .expect(&[LShift, Kb1])
+            .tick(1)
+            .expect(&[])
+            .tick(11)
+            .expect(&[LCtrl, Enter])
+            .tick(1)
+            .expect(&[]);
+    }
+
+    #[test]
+    fn test_twice() {
+        // The second one waits for the first
+        let scenario = Scenario::new(LAYERS).tap(0, 0).tap(0, 0).tick(100);
+        let typed = |keycodes: &[_]| scenario.sent(keycodes);
+        assert_eq!(typed(&[LShift, H]), 2);
+        assert_eq!(typed(&[LCtrl, Enter]), 2);
+    }
+
+    #[test]
+    fn test_unicode() {
+        static ARROWS: SequenceMacro = SequenceMacro {
+            steps: &[Step::Unicode("←→"), Step::Text("a")],
+        };
+        let mut typing = Typing::new(&ARROWS, InputMethod::Linux);
+        let mut reports = vec![];
+        while typing.advance(0) {
+            reports.push(typing.keycodes().to_vec());
+        }
+        assert!(typing.is_done());
+        assert_eq!(reports.len(), 2 * 12 + 2);
+        let digits = |i: usize| {
+            reports[i..i + 8]
+                .iter()
+                .step_by(2)
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(digits(2), [[Kb2], [Kb1], [Kb9], [Kb0]]);
+        assert_eq!(digits(14), [[Kb2], [Kb1], [Kb9], [Kb2]]);
+        assert_eq!(reports[24], [A]);
+    }
+
+    #[test]
+    fn test_slow_host() {
+        // Each report is read before the next one, and a key held while
+        // typing doesn't get in.
+        Scenario::new(LAYERS)
+            .polling(8)
+            .tap(0, 0)
+            .press(0, 1)
+            .expect(&[LShift, H])
+            .tick(5)
+            .expect(&[LShift, H])
+            .tick(1)
+            .expect(&[])
+            .tick(7)
+            .expect(&[])
+            .tick(1)
+            .expect(&[I])
+            // The key held is held back until the macro is typed
+            .tick(100)
+            .expect(&[A]);
+    }
+}
@@ -0,0 +1,141 @@
+//! Settings persisted in the flash store.
+
+use crate::storage::{Error, Flash, Store};
+
+/// Store keys of the settings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Key {
+    Debounce = 1,
+    TappingTerm = 2,
+    DefaultLayer = 3,
+    Features = 4,
+    Os = 5,
+    UnicodeInput = 6,
+    /// First of the keys of the dynamic macros, one per slot.
+    Macros = 16,
+}
+
+/// Bits of `Settings::features`.
+pub mod features {
+    /// Auto-shift, enabled at startup.
+    pub const AUTO_SHIFT: u32 = 1 << 0;
+    /// N-key rollover, used at startup instead of the boot report.
+    pub const NKRO: u32 = 1 << 1;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Settings {
+    /// Number of scans a key must be stable before being reported.
+    pub debounce: u16,
+    /// Tapping term of the hold-tap keys that don't set their own, in ms.
+    pub tapping_term: u16,
+    pub default_layer: u8,
+    /// Bit set of the enabled features.
+    pub features: u32,
+    /// Host OS profile, as `Os as u8`.
+    pub os: u8,
+    /// Unicode input method, as `InputMethod as u8`.
+    pub unicode_input: u8,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            debounce: 5,
+            tapping_term: 200,
+            default_layer: 0,
+            features: 0,
+            os: 0,
+            unicode_input: 0,
+        }
+    }
+}
+
+impl Settings {
+    /// Loads the settings, using the default for the missing ones.
+    pub fn load<F: Flash>(store: &Store<F>) -> Self {
+        let default = Self::default();
+        Settings {
+            debounce: load(store, Key::Debounce).map_or(default.debounce, u16::from_le_bytes),
+            tapping_term: load(store, Key::TappingTerm)
+                .map_or(default.tapping_term, u16::from_le_bytes),
+            default_layer: load(store, Key::DefaultLayer)
+                .map_or(default.default_layer, u8::from_le_bytes),
+            features: load(store, Key::Features).map_or(default.features, u32::from_le_bytes),
+            os: load(store, Key::Os).map_or(default.os, u8::from_le_bytes),
+            unicode_input: load(store, Key::UnicodeInput)
+                .map_or(default.unicode_input, u8::from_le_bytes),
+        }
+    }
+
+    /// Saves the settings. Only the changed ones are written.
+    pub fn save<F: Flash>(&self, store: &mut Store<F>) -> Result<(), Error> {
+        store.write(Key::Debounce as u8, &self.debounce.to_le_bytes())?;
+        store.write(Key::TappingTerm as u8, &self.tapping_term.to_le_bytes())?;
+        store.write(Key::DefaultLayer as u8, &self.default_layer.to_le_bytes())?;
+        store.write(Key::Features as u8, &self.features.to_le_bytes())?;
+        store.write(Key::Os as u8, &self.os.to_le_bytes())?;
+        store.write(Key::UnicodeInput as u8, &self.unicode_input.to_le_bytes())
+    }
+}
+
+fn load<F: Flash, const N: usize>(store: &Store<F>, key: Key) -> Option<[u8; N]> {
+    let mut buf = [0; N];
+    match store.read(key as u8, &mut buf) {
+        Some(len) if len == N => Some(buf),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct RamFlash([u32; 128]);
+
+    impl Flash for RamFlash {
+        const ERASE_SIZE: usize = 256;
+        fn capacity(&self) -> usize {
+            self.0.len() * 4
+        }
+        fn read(&self, offset: usize) -> u32 {
+            self.0[offset / 4]
+        }
+        fn write(&mut self, offset: usize, word: u32) -> Result<(), Error> {
+            self.0[offset / 4] = word;
+            Ok(())
+        }
+        fn erase(&mut self, offset: usize) -> Result<(), Error> {
+            for w in &mut self.0[offset / 4..(offset + 256) / 4] {
+                *w = crate::storage::ERASED;
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_load_save() {
+        let mut store = Store::mount(RamFlash([0; 128]), 256).unwrap();
+        assert_eq!(Settings::load(&store), Settings::default());
+
+        let settings = Settings {
+            debounce: 8,
+            tapping_term: 180,
+            default_layer: 2,
+            features: 0b101,
+            os: 1,
+            unicode_input: 2,
+        };
+        settings.save(&mut store).unwrap();
+        let store = Store::mount(store.release(), 256).unwrap();
+        assert_eq!(Settings::load(&store), settings);
+    }
+
+    #[test]
+    fn test_bad_length_is_ignored() {
+        let mut store = Store::mount(RamFlash([0; 128]), 256).unwrap();
+        store.write(Key::Debounce as u8, &[1]).unwrap();
+        assert_eq!(Settings::load(&store).debounce, 5);
+    }
+}
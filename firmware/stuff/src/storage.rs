@@ -0,0 +1,455 @@
+//! Log-structured key/value store, used to keep the settings in the
+//! internal flash.
+//!
+//! The storage area is split in sectors of one or more erase units.
+//! Records are appended to the active sector. When it is full, the
+//! live records are copied to the next sector, which then becomes the
+//! active one, so the sectors are erased in turn.
+//!
+//! A sector starts with a header word (magic and sequence number),
+//! written once the sector is complete. A record is a header word
+//! (magic, crc, length and key), the value padded to words, and a
+//! commit word written last. A power cut thus leaves either the old
+//! or the new value.
+
+use crate::crc8;
+
+/// Value of an erased flash word.
+pub const ERASED: u32 = 0xffff_ffff;
+/// Maximum length of a value, in bytes.
+pub const MAX_LEN: usize = 255;
+
+const SECTOR_MAGIC: u32 = 0x5e7b_0000;
+const RECORD_MAGIC: u32 = 0xa5;
+const COMMIT: u32 = 0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The flash reported an error while erasing or writing.
+    Flash,
+    /// The value does not fit in a sector.
+    TooLarge,
+    /// The live values do not leave room for the new one.
+    Full,
+}
+
+/// A flash area that can be erased by units and written by words.
+///
+/// Offsets are in bytes, relative to the start of the area.
+pub trait Flash {
+    /// Size of an erase unit, in bytes (a 256 bytes row on the SAMD21).
+    const ERASE_SIZE: usize;
+    /// Size of the area, in bytes.
+    fn capacity(&self) -> usize;
+    fn read(&self, offset: usize) -> u32;
+    /// Writes a word. The word must be erased.
+    fn write(&mut self, offset: usize, word: u32) -> Result<(), Error>;
+    /// Erases the unit starting at `offset`.
+    fn erase(&mut self, offset: usize) -> Result<(), Error>;
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Record {
+    offset: usize,
+    key: u8,
+    len: usize,
+}
+
+enum Slot {
+    Erased,
+    Corrupted,
+    Record(Option<Record>, usize),
+}
+
+pub struct Store<F> {
+    flash: F,
+    sector_size: usize,
+    active: usize,
+    seq: u16,
+    head: usize,
+}
+
+impl<F: Flash> Store<F> {
+    /// Opens the store, formatting the area if it holds no valid sector.
+    ///
+    /// `sector_size` must be a multiple of `F::ERASE_SIZE`, and the area
+    /// must hold at least two sectors.
+    pub fn mount(flash: F, sector_size: usize) -> Result<Self, Error> {
+        assert!(sector_size.is_multiple_of(F::ERASE_SIZE));
+        assert!(flash.capacity() / sector_size >= 2);
+        let mut store = Store {
+            flash,
+            sector_size,
+            active: 0,
+            seq: 0,
+            head: 4,
+        };
+        let mut found = false;
+        for sector in 0..store.sectors() {
+            let header = store.flash.read(sector * sector_size);
+            if header & 0xffff_0000 != SECTOR_MAGIC {
+                continue;
+            }
+            let seq = header as u16;
+            if !found || seq.wrapping_sub(store.seq) as i16 > 0 {
+                found = true;
+                store.active = sector;
+                store.seq = seq;
+            }
+        }
+        if found {
+            store.head = store.log_end();
+        } else {
+            store.erase_sector(0)?;
+            store.flash.write(0, SECTOR_MAGIC)?;
+        }
+        Ok(store)
+    }
+
+    /// Reads the value of `key` into `buf`, returning its length.
+    pub fn read(&self, key: u8, buf: &mut [u8]) -> Option<usize> {
+        let record = self.find(key)?;
+        let base = self.base(self.active) + record.offset + 4;
+        for (i, b) in buf.iter_mut().take(record.len).enumerate() {
+            *b = self.byte(base, i);
+        }
+        Some(record.len)
+    }
+
+    /// Writes the value of `key`. Nothing is written if the value is
+    /// unchanged.
+    pub fn write(&mut self, key: u8, value: &[u8]) -> Result<(), Error> {
+        let size = record_size(value.len());
+        if value.len() > MAX_LEN || size > self.sector_size - 4 {
+            return Err(Error::TooLarge);
+        }
+        if self.find(key).is_some_and(|r| self.contains(r, value)) {
+            return Ok(());
+        }
+        if self.head + size > self.sector_size {
+            self.collect()?;
+            if self.head + size > self.sector_size {
+                return Err(Error::Full);
+            }
+        }
+        let offset = self.base(self.active) + self.head;
+        self.head += size;
+        let crc = value
+            .iter()
+            .fold(crc(key, value.len()), |c, &b| crc8::MAXIM.calc_byte(c, b));
+        let header =
+            RECORD_MAGIC << 24 | (crc as u32) << 16 | (value.len() as u32) << 8 | key as u32;
+        self.flash.write(offset, header)?;
+        for (i, chunk) in value.chunks(4).enumerate() {
+            let word = chunk.iter().enumerate().fold(ERASED, |w, (j, &b)| {
+                w & !(0xff << (j * 8)) | (b as u32) << (j * 8)
+            });
+            self.flash.write(offset + 4 + i * 4, word)?;
+        }
+        self.flash.write(offset + size - 4, COMMIT)
+    }
+
+    /// Gives back the underlying flash.
+    pub fn release(self) -> F {
+        self.flash
+    }
+
+    fn sectors(&self) -> usize {
+        self.flash.capacity() / self.sector_size
+    }
+
+    fn base(&self, sector: usize) -> usize {
+        sector * self.sector_size
+    }
+
+    fn byte(&self, base: usize, i: usize) -> u8 {
+        (self.flash.read(base + i / 4 * 4) >> (i % 4 * 8)) as u8
+    }
+
+    fn contains(&self, record: Record, value: &[u8]) -> bool {
+        let base = self.base(self.active) + record.offset + 4;
+        record.len == value.len()
+            && value
+                .iter()
+                .enumerate()
+                .all(|(i, &b)| self.byte(base, i) == b)
+    }
+
+    fn slot(&self, sector: usize, offset: usize) -> Slot {
+        if offset + 4 > self.sector_size {
+            return Slot::Corrupted;
+        }
+        let base = self.base(sector);
+        let header = self.flash.read(base + offset);
+        if header == ERASED {
+            return Slot::Erased;
+        }
+        if header >> 24 != RECORD_MAGIC {
+            return Slot::Corrupted;
+        }
+        let key = header as u8;
+        let len = (header >> 8) as u8 as usize;
+        let next = offset + record_size(len);
+        if next > self.sector_size {
+            return Slot::Corrupted;
+        }
+        let data = base + offset + 4;
+        let crc = (0..len).fold(crc(key, len), |c, i| {
+            crc8::MAXIM.calc_byte(c, self.byte(data, i))
+        });
+        let committed = self.flash.read(base + next - 4) == COMMIT && crc == (header >> 16) as u8;
+        let record = committed.then_some(Record { offset, key, len });
+        Slot::Record(record, next)
+    }
+
+    /// Offset of the first free word of the active sector, or the sector
+    /// size if the log is corrupted, forcing a collection on next write.
+    fn log_end(&self) -> usize {
+        let mut offset = 4;
+        loop {
+            match self.slot(self.active, offset) {
+                Slot::Erased => return offset,
+                Slot::Corrupted => return self.sector_size,
+                Slot::Record(_, next) => offset = next,
+            }
+        }
+    }
+
+    /// Visits the committed records of `sector`, in write order.
+    fn for_each(&self, sector: usize, mut f: impl FnMut(Record)) {
+        let mut offset = 4;
+        while let Slot::Record(record, next) = self.slot(sector, offset) {
+            if let Some(record) = record {
+                f(record);
+            }
+            offset = next;
+        }
+    }
+
+    fn find(&self, key: u8) -> Option<Record> {
+        let mut found = None;
+        self.for_each(self.active, |r| {
+            if r.key == key {
+                found = Some(r);
+            }
+        });
+        found
+    }
+
+    fn erase_sector(&mut self, sector: usize) -> Result<(), Error> {
+        let base = self.base(sector);
+        for unit in (0..self.sector_size).step_by(F::ERASE_SIZE) {
+            self.flash.erase(base + unit)?;
+        }
+        Ok(())
+    }
+
+    /// Copies the live records to the next sector and makes it active.
+    fn collect(&mut self) -> Result<(), Error> {
+        let next = (self.active + 1) % self.sectors();
+        self.erase_sector(next)?;
+        let (from, to) = (self.base(self.active), self.base(next));
+        let mut live = [false; 256];
+        self.for_each(self.active, |r| live[r.key as usize] = true);
+        let mut head = 4;
+        for key in (0..256).filter(|&k| live[k]) {
+            let record = self.find(key as u8).unwrap();
+            let size = record_size(record.len);
+            for i in (0..size).step_by(4) {
+                let word = self.flash.read(from + record.offset + i);
+                self.flash.write(to + head + i, word)?;
+            }
+            head += size;
+        }
+        let seq = self.seq.wrapping_add(1);
+        self.flash.write(to, SECTOR_MAGIC | seq as u32)?;
+        self.active = next;
+        self.seq = seq;
+        self.head = head;
+        Ok(())
+    }
+}
+
+fn record_size(len: usize) -> usize {
+    4 + len.div_ceil(4) * 4 + 4
+}
+
+fn crc(key: u8, len: usize) -> u8 {
+    crc8::MAXIM.calc_buf(&[key, len as u8])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROW: usize = 256;
+
+    #[derive(Clone)]
+    struct MockFlash {
+        mem: Vec<u32>,
+        erases: Vec<usize>,
+        ops_left: Option<usize>,
+    }
+
+    impl MockFlash {
+        fn new(rows: usize) -> Self {
+            MockFlash {
+                mem: vec![ERASED; rows * ROW / 4],
+                erases: vec![0; rows],
+                ops_left: None,
+            }
+        }
+        fn power(&mut self) -> Result<(), Error> {
+            match &mut self.ops_left {
+                Some(0) => Err(Error::Flash),
+                Some(n) => {
+                    *n -= 1;
+                    Ok(())
+                }
+                None => Ok(()),
+            }
+        }
+    }
+
+    impl Flash for MockFlash {
+        const ERASE_SIZE: usize = ROW;
+        fn capacity(&self) -> usize {
+            self.mem.len() * 4
+        }
+        fn read(&self, offset: usize) -> u32 {
+            self.mem[offset / 4]
+        }
+        fn write(&mut self, offset: usize, word: u32) -> Result<(), Error> {
+            self.power()?;
+            assert_eq!(self.mem[offset / 4], ERASED, "write to a dirty word");
+            self.mem[offset / 4] = word;
+            Ok(())
+        }
+        fn erase(&mut self, offset: usize) -> Result<(), Error> {
+            self.power()?;
+            assert_eq!(offset % ROW, 0);
+            self.erases[offset / ROW] += 1;
+            for w in &mut self.mem[offset / 4..(offset + ROW) / 4] {
+                *w = ERASED;
+            }
+            Ok(())
+        }
+    }
+
+    fn get<F: Flash>(store: &Store<F>, key: u8) -> Option<Vec<u8>> {
+        let mut buf = [0; MAX_LEN];
+        store.read(key, &mut buf).map(|len| buf[..len].to_vec())
+    }
+
+    #[test]
+    fn test_read_write() {
+        let mut store = Store::mount(MockFlash::new(4), ROW).unwrap();
+        assert_eq!(get(&store, 1), None);
+        store.write(1, &[5, 0]).unwrap();
+        store.write(2, b"hello").unwrap();
+        store.write(3, &[]).unwrap();
+        assert_eq!(get(&store, 1), Some(vec![5, 0]));
+        assert_eq!(get(&store, 2), Some(b"hello".to_vec()));
+        assert_eq!(get(&store, 3), Some(vec![]));
+        store.write(1, &[10, 0]).unwrap();
+        assert_eq!(get(&store, 1), Some(vec![10, 0]));
+
+        let store = Store::mount(store.release(), ROW).unwrap();
+        assert_eq!(get(&store, 1), Some(vec![10, 0]));
+        assert_eq!(get(&store, 2), Some(b"hello".to_vec()));
+    }
+
+    #[test]
+    fn test_unchanged_value_is_not_written() {
+        let mut store = Store::mount(MockFlash::new(2), ROW).unwrap();
+        store.write(1, &[1, 2, 3]).unwrap();
+        let head = store.head;
+        store.write(1, &[1, 2, 3]).unwrap();
+        assert_eq!(store.head, head);
+    }
+
+    #[test]
+    fn test_too_large() {
+        let mut store = Store::mount(MockFlash::new(2), ROW).unwrap();
+        assert_eq!(store.write(1, &[0; 250]), Err(Error::TooLarge));
+        let mut store = Store::mount(MockFlash::new(4), 2 * ROW).unwrap();
+        assert_eq!(store.write(1, &[0; 250]), Ok(()));
+    }
+
+    #[test]
+    fn test_collect_and_wear_levelling() {
+        let mut store = Store::mount(MockFlash::new(4), ROW).unwrap();
+        store.write(7, b"constant").unwrap();
+        for i in 0..1000u16 {
+            store.write(1, &i.to_le_bytes()).unwrap();
+            store.write(2, &(i / 3).to_le_bytes()).unwrap();
+            assert_eq!(get(&store, 1), Some(i.to_le_bytes().to_vec()));
+        }
+        assert_eq!(get(&store, 7), Some(b"constant".to_vec()));
+        let store = Store::mount(store.release(), ROW).unwrap();
+        assert_eq!(get(&store, 1), Some(999u16.to_le_bytes().to_vec()));
+        assert_eq!(get(&store, 2), Some(333u16.to_le_bytes().to_vec()));
+        let erases = store.release().erases;
+        let (min, max) = (erases.iter().min().unwrap(), erases.iter().max().unwrap());
+        assert!(*min > 0 && max - min <= 1, "{:?}", erases);
+    }
+
+    #[test]
+    fn test_full() {
+        let mut store = Store::mount(MockFlash::new(2), ROW).unwrap();
+        for key in 0..4 {
+            store.write(key, &[key; 40]).unwrap();
+        }
+        assert_eq!(store.write(4, &[4; 40]), Ok(()));
+        assert_eq!(store.write(5, &[5; 40]), Err(Error::Full));
+        assert_eq!(get(&store, 0), Some(vec![0; 40]));
+    }
+
+    /// Cuts the power before each flash operation of a sequence of
+    /// writes, and checks that the mounted store always holds the values
+    /// of a prefix of the sequence, and still works afterwards.
+    #[test]
+    fn test_power_cut() {
+        let mut store = Store::mount(MockFlash::new(3), ROW).unwrap();
+        for i in 0..12u8 {
+            store.write(i % 3, &[i; 9]).unwrap();
+        }
+        let flash = store.release();
+        let writes: Vec<(u8, Vec<u8>)> = (0..30u8)
+            .map(|i| (i % 4, vec![100 + i; 1 + i as usize % 11]))
+            .collect();
+
+        let state = |store: &Store<MockFlash>| (0..4).map(|k| get(store, k)).collect::<Vec<_>>();
+        let mut counter = flash.clone();
+        counter.ops_left = Some(usize::MAX);
+        let mut store = Store::mount(counter, ROW).unwrap();
+        let mut expected = vec![state(&store)];
+        for (key, value) in &writes {
+            store.write(*key, value).unwrap();
+            expected.push(state(&store));
+        }
+        let total = usize::MAX - store.release().ops_left.unwrap();
+
+        for cut in 0..=total {
+            let mut flash = flash.clone();
+            flash.ops_left = Some(cut);
+            let mut store = Store::mount(flash, ROW).unwrap();
+            let done = writes
+                .iter()
+                .take_while(|(key, value)| store.write(*key, value).is_ok())
+                .count();
+            let mut flash = store.release();
+            flash.ops_left = None;
+            let mut store = Store::mount(flash, ROW).unwrap();
+            let found = state(&store);
+            assert!(
+                found == expected[done] || found == expected[(done + 1).min(writes.len())],
+                "cut before operation {}: {:?}",
+                cut,
+                found
+            );
+            store.write(3, b"after").unwrap();
+            assert_eq!(get(&store, 3), Some(b"after".to_vec()));
+        }
+    }
+}
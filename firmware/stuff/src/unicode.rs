@@ -0,0 +1,183 @@
+//! Unicode input: characters typed by their code point, with the input
+//! method of the host.
+//!
+//! - Linux (IBus, GTK): Ctrl+Shift+U, the hex digits, then space.
+//! - macOS, with the "Unicode Hex Input" source: the 4 hex digits of
+//!   each UTF-16 unit with Option held.
+//! - Windows, with WinCompose and its default compose key: right Alt,
+//!   U, the hex digits, then enter.
+//!
+//! A character makes a series of reports, that sequence macros send one
+//! at a time.
+
+use arrayvec::ArrayVec;
+use keyberon::key_code::KeyCode::{self, *};
+
+/// Maximum number of reports of a character, with the surrogate pairs
+/// of macOS.
+pub const MAX_REPORTS: usize = 18;
+
+/// The keycodes of a report.
+pub type Report = ArrayVec<KeyCode, 3>;
+
+pub type Reports = ArrayVec<Report, MAX_REPORTS>;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum InputMethod {
+    #[default]
+    Linux = 0,
+    MacOs = 1,
+    WinCompose = 2,
+}
+
+impl InputMethod {
+    /// The input method stored as `n` in the settings, if any.
+    pub fn from_u8(n: u8) -> Option<Self> {
+        match n {
+            0 => Some(InputMethod::Linux),
+            1 => Some(InputMethod::MacOs),
+            2 => Some(InputMethod::WinCompose),
+            _ => None,
+        }
+    }
+}
+
+/// The reports typing `c` with `method`.
+pub fn reports(c: char, method: InputMethod) -> Reports {
+    let mut reports = Reports::new();
+    let mut tap = |keycodes: &[KeyCode], held: &[KeyCode]| {
+        reports.push(held.iter().chain(keycodes).copied().collect());
+        reports.push(held.iter().copied().collect());
+    };
+    match method {
+        InputMethod::Linux => {
+            tap(&[LCtrl, LShift, U], &[]);
+            hex(c as u32, 4, |kc| tap(&[kc], &[]));
+            tap(&[Space], &[]);
+        }
+        InputMethod::MacOs => {
+            let mut units = [0; 2];
+            for &unit in c.encode_utf16(&mut units).iter() {
+                hex(unit as u32, 4, |kc| tap(&[kc], &[LAlt]));
+            }
+            reports.insert(0, [LAlt].iter().copied().collect());
+            reports.push(Report::new());
+        }
+        InputMethod::WinCompose => {
+            tap(&[RAlt], &[]);
+            tap(&[U], &[]);
+            hex(c as u32, 1, |kc| tap(&[kc], &[]));
+            tap(&[Enter], &[]);
+        }
+    }
+    reports
+}
+
+/// Calls `f` with the keys of the hex digits of `n`, at least `digits`
+/// of them.
+fn hex(n: u32, digits: usize, mut f: impl FnMut(KeyCode)) {
+    const DIGITS: [KeyCode; 16] = [
+        Kb0, Kb1, Kb2, Kb3, Kb4, Kb5, Kb6, Kb7, Kb8, Kb9, A, B, C, D, E, F,
+    ];
+    let mut len = digits;
+    while len < 8 && n >> (len * 4) != 0 {
+        len += 1;
+    }
+    for i in (0..len).rev() {
+        f(DIGITS[(n >> (i * 4)) as usize & 0xf]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::action::{CustomAction, Layers};
+    use crate::scenario::Scenario;
+    use crate::settings::Settings;
+    use keyberon::action::Action::*;
+    use keyberon::key_code::KeyCode;
+    use keyberon_macros::layout;
+
+    static LAYERS: Layers = layout! {
+        {[
+            {Custom(CustomAction::Unicode('é'))}
+            {Custom(CustomAction::UnicodeInput(InputMethod::WinCompose))}
+        ]}
+    };
+
+    fn reports(c: char, method: InputMethod) -> Vec<Vec<KeyCode>> {
+        super::reports(c, method)
+            .iter()
+            .map(|r| r.to_vec())
+            .collect()
+    }
+
+    #[test]
+    fn test_linux() {
+        #[rustfmt::skip]
+        let expected = [
+            vec![LCtrl, LShift, U], vec![],
+            vec![Kb2], vec![], vec![Kb1], vec![], vec![Kb9], vec![], vec![Kb2], vec![],
+            vec![Space], vec![],
+        ];
+        assert_eq!(reports('→', InputMethod::Linux), expected);
+    }
+
+    #[test]
+    fn test_mac_os() {
+        #[rustfmt::skip]
+        let expected = [
+            vec![LAlt],
+            vec![LAlt, Kb0], vec![LAlt], vec![LAlt, Kb3], vec![LAlt],
+            vec![LAlt, B], vec![LAlt], vec![LAlt, B], vec![LAlt],
+            vec![],
+        ];
+        assert_eq!(reports('λ', InputMethod::MacOs), expected);
+        // A surrogate pair
+        let emoji = reports('😀', InputMethod::MacOs);
+        assert_eq!(emoji.len(), MAX_REPORTS);
+        assert_eq!(emoji[1], [LAlt, D]);
+        assert_eq!(emoji[9], [LAlt, D]);
+        assert_eq!(emoji[15], [LAlt, Kb0]);
+    }
+
+    #[test]
+    fn test_win_compose() {
+        #[rustfmt::skip]
+        let expected = [
+            vec![RAlt], vec![], vec![U], vec![],
+            vec![E], vec![], vec![Kb9], vec![],
+            vec![Enter], vec![],
+        ];
+        assert_eq!(reports('é', InputMethod::WinCompose), expected);
+        let max = super::reports('\u{10ffff}', InputMethod::WinCompose);
+        assert_eq!(max.len(), MAX_REPORTS);
+    }
+
+    #[test]
+    fn test_unicode() {
+        let mac_os = Settings {
+            unicode_input: InputMethod::MacOs as u8,
+            ..Settings::default()
+        };
+        let scenario = Scenario::with_settings(LAYERS, &mac_os).tap(0, 0).tick(20);
+        assert_eq!(scenario.sent(&[LAlt, E]), 1);
+        assert_eq!(scenario.sent(&[LAlt, Kb9]), 1);
+        // Then with WinCompose, as chosen by the key
+        let scenario = scenario.tap(0, 1).tap(0, 0).tick(20);
+        assert_eq!(scenario.sent(&[RAlt]), 1);
+        assert_eq!(scenario.sent(&[E]), 1);
+        assert_eq!(scenario.sent(&[Enter]), 1);
+    }
+
+    #[test]
+    fn test_in_turn() {
+        // The second character waits for the first to be typed
+        let scenario = Scenario::new(LAYERS).tap(0, 0).tap(0, 0).tick(50);
+        let typed = |keycodes: &[_]| scenario.sent(keycodes);
+        assert_eq!(typed(&[LCtrl, LShift, U]), 2);
+        assert_eq!(typed(&[Kb9]), 2);
+        assert_eq!(typed(&[Space]), 2);
+    }
+}